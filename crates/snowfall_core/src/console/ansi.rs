@@ -0,0 +1,163 @@
+//! Helpers for measuring and padding strings that already contain ANSI escape
+//! codes, such as those built with `cformat`, e.g. to line up colored columns
+//! into a table.
+
+use unicode_width::UnicodeWidthChar;
+
+const RESET: &str = "\x1b[0m";
+
+/// A piece of a string: either a printed character or a whole escape code
+enum Token<'a> {
+    Char(char),
+    Escape(&'a str),
+}
+
+/// Splits the string into characters and escape codes. A CSI sequence (like
+/// `\x1b[38;2;0;0;0m`) is one escape; any other escape is the `ESC` and the
+/// character after it.
+fn tokens(s: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let mut chars = rest.chars();
+        let c = chars.next()?;
+        if c != '\x1b' {
+            rest = chars.as_str();
+            return Some(Token::Char(c));
+        }
+        if chars.next() == Some('[') {
+            // Parameter and intermediate bytes run until a final byte in
+            // the range `@` to `~`
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+        let len = rest.len() - chars.as_str().len();
+        let escape = &rest[..len];
+        rest = chars.as_str();
+        Some(Token::Escape(escape))
+    })
+}
+
+/// Returns the string with its ANSI CSI sequences (like `\x1b[38;2;0;0;0m`)
+/// removed. Any other escape drops the `ESC` and the character after it.
+pub fn strip_ansi(s: &str) -> String {
+    tokens(s)
+        .filter_map(|token| match token {
+            Token::Char(c) => Some(c),
+            Token::Escape(_) => None,
+        })
+        .collect()
+}
+
+/// The number of terminal columns the string takes up once printed: escape
+/// codes, control characters, and combining marks take none, and wide
+/// characters such as CJK ideographs and most emoji take two.
+pub fn visible_width(s: &str) -> usize {
+    strip_ansi(s).chars().map(char_width).sum()
+}
+
+/// Pads the string with spaces on the right up to `width` visible columns
+pub fn pad_right(s: &str, width: usize) -> String {
+    let padding = width.saturating_sub(visible_width(s));
+    format!("{}{}", s, " ".repeat(padding))
+}
+
+/// Pads the string with spaces on the left up to `width` visible columns
+pub fn pad_left(s: &str, width: usize) -> String {
+    let padding = width.saturating_sub(visible_width(s));
+    format!("{}{}", " ".repeat(padding), s)
+}
+
+/// Shortens the string to at most `width` visible columns, ending it with
+/// `…` if anything was cut. Escape codes are kept, and a cut string that had
+/// any ends with a reset so its colors don't run on.
+pub fn truncate_visible(s: &str, width: usize) -> String {
+    if visible_width(s) <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    let mut used = 0;
+    let mut has_escapes = false;
+    for token in tokens(s) {
+        match token {
+            Token::Escape(escape) => {
+                out.push_str(escape);
+                has_escapes = true;
+            }
+            Token::Char(c) => {
+                let w = char_width(c);
+                if used + w > width - 1 {
+                    break;
+                }
+                out.push(c);
+                used += w;
+            }
+        }
+    }
+    out.push('…');
+    if has_escapes {
+        out.push_str(RESET);
+    }
+    out
+}
+
+/// The columns a single character takes up, with control characters taking
+/// none
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi_removes_escape_codes() {
+        assert_eq!(
+            strip_ansi("\x1b[38;2;255;0;0mred\x1b[0m and \x1b[1m\x1b[4mbold\x1b[0m"),
+            "red and bold"
+        );
+        assert_eq!(strip_ansi("plain [text](tag)"), "plain [text](tag)");
+        assert_eq!(strip_ansi("cut off \x1b[38;2"), "cut off ");
+    }
+
+    #[test]
+    fn test_visible_width_ignores_escapes() {
+        assert_eq!(visible_width("\x1b[38;5;208mabc\x1b[0m"), 3);
+        assert_eq!(visible_width("日本語"), 6);
+        assert_eq!(visible_width("e\u{301}!"), 2);
+        assert_eq!(visible_width("🌊 sea-git-sync"), 15);
+        assert_eq!(visible_width("\x1b[1m✔\x1b[0m 한국어"), 8);
+        assert_eq!(visible_width("tab\there"), 7);
+        assert_eq!(visible_width(""), 0);
+    }
+
+    #[test]
+    fn test_pad_measures_visible_width() {
+        let red = "\x1b[38;2;255;0;0mab\x1b[0m";
+        assert_eq!(pad_right("ab", 4), "ab  ");
+        assert_eq!(pad_right(red, 4), format!("{red}  "));
+        assert_eq!(pad_left(red, 4), format!("  {red}"));
+        assert_eq!(pad_left("日本", 5), " 日本");
+        assert_eq!(pad_right("abcdef", 4), "abcdef");
+    }
+
+    #[test]
+    fn test_truncate_visible() {
+        assert_eq!(truncate_visible("abcdef", 6), "abcdef");
+        assert_eq!(truncate_visible("abcdef", 4), "abc…");
+        assert_eq!(truncate_visible("abcdef", 0), "");
+        assert_eq!(truncate_visible("日本語", 4), "日…");
+        assert_eq!(
+            truncate_visible("\x1b[1mab\x1b[0m\x1b[31mcdef", 4),
+            "\x1b[1mab\x1b[0m\x1b[31mc…\x1b[0m"
+        );
+        assert_eq!(visible_width(&truncate_visible("\x1b[31mabcdef", 3)), 3);
+    }
+}
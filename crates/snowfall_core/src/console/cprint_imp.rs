@@ -6,50 +6,192 @@
 //! tag like `filename`, `filepath`, or `number`. The tag can both change
 //! the color of the text as well as apply formatting to the text.
 //!
+//! A tag can also list the styles `bold`, `italic`, and `underline`,
+//! separated by commas, e.g. `[x](red,bold,underline)`. Styles combine,
+//! while the last color listed wins; a tag with only styles keeps the
+//! surrounding color. A color with a `bg:` prefix sets the background
+//! instead, e.g. `[ PASS ](white,bg:green)`.
+//!
+//! More semantic tags can be added with `register_formatter`.
+//!
+//! A `dim:` percent blends the tag's color that far toward the
+//! `dim_background` color (black unless changed), e.g. `[note](blue,dim:50)`.
+//! With no color in the tag, it uses the terminal's faint style instead.
+//!
+//! Hex colors can have an alpha, as in `#f008` or `#ff000080`, to blend them
+//! over the `dim_background` color the same way.
+//!
+//! A tag that is none of these, like the link of a markdown link, is printed
+//! as written unless `set_unknown_tags` says to strip it, and `try_cformat`
+//! reports it as an error.
+//!
+//! The `number` tag groups digits with commas, and a locale suffix groups
+//! them another way: `[1000000](number:de)` prints `1.000.000`, and `fr`
+//! separates groups with spaces.
+//!
+//! The `percent` tag shows a fraction as a percentage with one decimal, so
+//! `[0.873](percent)` prints `87.3%`, and a suffix sets the decimals, as in
+//! `[0.5](percent:0)` for `50%`.
+//!
+//! The `duration` tag shows a number of seconds as `to_pretty_duration`
+//! does, so `[83.4](duration)` prints `1m 23.4s`.
+//!
+//! A bracket escaped with a backslash is printed as-is rather than starting
+//! or ending a tag, so `\[text](red)` prints `[text](red)`.
+//!
+//! A `gradient:` tag with two colors, e.g. `[~~~~~~~~](gradient:#39F..#7AF)`,
+//! fades the text from the first color to the second one character at a
+//! time. Text of a single character gets the first color.
+//!
+//! Tags can nest, e.g. `[outer [inner](red) text](blue)`. The inner tag
+//! applies on top of the outer one for its span (so `inner` is red and keeps
+//! any outer styles), and the outer formatting resumes after it.
+//!
 //! The implementation was partly generated using Gemini 2.5 Pro. Apologies :)
 //!
 
 use std::collections::HashMap;
-use std::sync::{Mutex, OnceLock};
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::strings::{to_comma_string_with, to_pretty_duration};
 
 //===========================================================================//
 // Custom color storage (dynamic, with aliasing and runtime add)
 //===========================================================================//
 
+/// The colors the table starts with, and goes back to on `reset_colors`
+const DEFAULT_COLORS: &[(&str, &str)] = &[
+    ("h1", "#fff"),
+    ("txt,text", "#bbb"),
+    ("error", "#f00"),
+    ("warn", "#ffea00"),
+    ("key", "#4CF"),
+    ("opt,option", "#78aeff"),
+    ("filename", "#e0c16c"),
+    ("filename_prefix", "#ed552b"),
+    ("command", "#dbd488"),
+    ("success", "#32CD32"),
+    ("success_dim", "#80ad80"),
+    ("dim_background", "#000"),
+];
+
+/// The table itself is created once, but its contents can be swapped out
+/// at any time with `set_base_colors` or `reset_colors`
 static CUSTOM_COLORS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
 
 pub fn ensure_custom_colors() -> &'static Mutex<HashMap<String, String>> {
-    CUSTOM_COLORS.get_or_init(|| {
-        let table = vec![
-            ("h1", "#fff"),
-            ("txt,text", "#bbb"),
-            ("error", "#f00"),
-            ("warn", "#ffea00"),
-            ("key", "#4CF"),
-            ("opt,option", "#78aeff"),
-            ("filename", "#e0c16c"),
-            ("command", "#dbd488"),
-            ("success", "#32CD32"),
-            ("success_dim", "#80ad80"),
-        ];
-        let mut colors = HashMap::new();
-        for (key, val) in table {
-            for part in key.split(',') {
-                let trimmed = part.trim();
-                if !trimmed.is_empty() {
-                    colors.insert(trimmed.to_string(), val.to_string());
-                }
-                if trimmed == "text" {
-                    colors.insert("".to_string(), val.to_string());
-                }
+    CUSTOM_COLORS.get_or_init(|| Mutex::new(build_color_table(DEFAULT_COLORS)))
+}
+
+/// Replaces the whole color table, including any added colors, with the
+/// given entries. As in the default table, a key can list comma-separated
+/// aliases, and `text` also becomes the color for an empty tag.
+pub fn set_base_colors(entries: &[(&str, &str)]) {
+    let mut colors_guard = ensure_custom_colors()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    *colors_guard = build_color_table(entries);
+}
+
+/// Puts the color table back to the default colors, dropping any added ones
+pub fn reset_colors() {
+    set_base_colors(DEFAULT_COLORS);
+}
+
+fn build_color_table(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    let mut colors = HashMap::new();
+    for (key, val) in entries {
+        for part in key.split(',') {
+            let trimmed = part.trim();
+            if !trimmed.is_empty() {
+                colors.insert(trimmed.to_string(), val.to_string());
+            }
+            if trimmed == "text" {
+                colors.insert("".to_string(), val.to_string());
             }
         }
-        Mutex::new(colors)
-    })
+    }
+    colors
+}
+
+//===========================================================================//
+// Custom formatter storage (semantic tags registered at runtime)
+//===========================================================================//
+
+type Formatter = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+static CUSTOM_FORMATTERS: OnceLock<Mutex<HashMap<String, Formatter>>> = OnceLock::new();
+
+fn ensure_custom_formatters() -> &'static Mutex<HashMap<String, Formatter>> {
+    CUSTOM_FORMATTERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Add a semantic tag that formats its text with `format`, e.g. a `sha` tag
+/// that abbreviates commit hashes. It takes precedence over a built-in tag
+/// of the same name, and on its own keeps the surrounding color.
+pub fn register_formatter<F>(name: &str, format: F)
+where
+    F: Fn(&str) -> String + Send + Sync + 'static,
+{
+    if let Ok(mut formatters_guard) = ensure_custom_formatters().lock() {
+        formatters_guard.insert(name.to_string(), Arc::new(format));
+    }
+}
+
+fn custom_formatter(name: &str) -> Option<Formatter> {
+    // Cloned out so the formatter runs without the lock held, in case it
+    // formats text itself
+    ensure_custom_formatters()
+        .lock()
+        .ok()
+        .and_then(|formatters_guard| formatters_guard.get(name).cloned())
+}
+
+/// Held by tests that replace the color table, or that rely on colors other
+/// tests would lose when it is replaced
+#[cfg(test)]
+pub(crate) static COLOR_TABLE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The error from `cprint_add_color` when the value isn't a color
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidColor {
+    pub name: String,
+    pub value: String,
 }
 
-/// Add a custom color to the global color table
-pub fn cprint_add_color(name: &str, value: &str) {
+impl std::fmt::Display for InvalidColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid color '{}' for '{}': expected a hex color, a named color, or an existing custom color",
+            self.value, self.name
+        )
+    }
+}
+
+impl std::error::Error for InvalidColor {}
+
+/// Add a custom color to the global color table. The value can be a hex
+/// color, a named color, or the name of another custom color, and is
+/// rejected if it is none of these.
+pub fn cprint_add_color(name: &str, value: &str) -> Result<(), InvalidColor> {
+    if parse_color(value).is_none() {
+        return Err(InvalidColor {
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+    }
+    cprint_add_color_unchecked(name, value);
+    Ok(())
+}
+
+/// Add a custom color to the global color table without checking the value,
+/// which falls back to gray when printed if it isn't a color
+pub fn cprint_add_color_unchecked(name: &str, value: &str) {
     let colors = ensure_custom_colors();
     if let Ok(mut colors_guard) = colors.lock() {
         colors_guard.insert(name.to_string(), value.to_string());
@@ -62,41 +204,331 @@ const RESET: &str = "\x1b[0m";
 // Public symbols
 //===========================================================================//
 
+/// Whether `cprint_imp` emits colors: never with `NO_COLOR` set (even along
+/// with `CLICOLOR_FORCE`), always with `CLICOLOR_FORCE` set (to anything but
+/// `0`), and otherwise only when stdout is a terminal.
+pub fn color_enabled() -> bool {
+    color_enabled_for(std::io::stdout().is_terminal())
+}
+
+/// Like `color_enabled`, but for a stream that may or may not be a terminal
+fn color_enabled_for(is_terminal: bool) -> bool {
+    color_enabled_with(
+        std::env::var_os("NO_COLOR").as_deref(),
+        std::env::var_os("CLICOLOR_FORCE").as_deref(),
+        is_terminal,
+    )
+}
+
+/// The decision of `color_enabled_for`, given the values of `NO_COLOR` and
+/// `CLICOLOR_FORCE`
+fn color_enabled_with(
+    no_color: Option<&std::ffi::OsStr>,
+    clicolor_force: Option<&std::ffi::OsStr>,
+    is_terminal: bool,
+) -> bool {
+    if no_color.is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if clicolor_force.is_some_and(|v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    is_terminal
+}
+
+/// How many colors the terminal can show, which decides whether colors are
+/// emitted as-is or mapped to the nearest color the terminal has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 24-bit RGB colors
+    TrueColor,
+    /// The xterm 256-color palette
+    Ansi256,
+    /// The 16 standard ANSI colors
+    Ansi16,
+}
+
+static COLOR_MODE: Mutex<Option<ColorMode>> = Mutex::new(None);
+
+/// Force a color mode instead of detecting it, or go back to detecting it
+/// with `None`
+pub fn set_color_mode(mode: Option<ColorMode>) {
+    if let Ok(mut guard) = COLOR_MODE.lock() {
+        *guard = mode;
+    }
+}
+
+/// The color mode set with `set_color_mode`, or else detected from the
+/// environment: `COLORTERM=truecolor` (or `24bit`) means true color, a `TERM`
+/// like `xterm-256color` means 256 colors, and any other `TERM` means 16
+/// colors. Without a `TERM` at all, as on Windows, true color is assumed.
+pub fn color_mode() -> ColorMode {
+    if let Ok(guard) = COLOR_MODE.lock()
+        && let Some(mode) = *guard
+    {
+        return mode;
+    }
+    let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+    if colorterm == "truecolor" || colorterm == "24bit" {
+        return ColorMode::TrueColor;
+    }
+    match std::env::var("TERM") {
+        Ok(term) if term.contains("256color") => ColorMode::Ansi256,
+        Ok(term) if !term.is_empty() => ColorMode::Ansi16,
+        _ => ColorMode::TrueColor,
+    }
+}
+
+static DEBUG_OUTPUT: Mutex<Option<bool>> = Mutex::new(None);
+
+/// Turn `debugln!` output on or off instead of going by `SNOWFALL_DEBUG`, or
+/// go back to it with `None`
+pub fn set_debug_output(enabled: Option<bool>) {
+    if let Ok(mut guard) = DEBUG_OUTPUT.lock() {
+        *guard = enabled;
+    }
+}
+
+/// Whether `debugln!` prints: as set with `set_debug_output`, or else only
+/// with `SNOWFALL_DEBUG` set (to anything but `0`)
+pub fn debug_output() -> bool {
+    if let Ok(guard) = DEBUG_OUTPUT.lock()
+        && let Some(enabled) = *guard
+    {
+        return enabled;
+    }
+    std::env::var_os("SNOWFALL_DEBUG").is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+static ABBREVIATE_PATHS: AtomicBool = AtomicBool::new(true);
+
+/// Whether the `filename` and `filepath` tags shorten paths in the working
+/// directory to start with `.` and paths in the home directory to start
+/// with `~`, which they do by default
+pub fn set_abbreviate_paths(enabled: bool) {
+    ABBREVIATE_PATHS.store(enabled, Ordering::Relaxed);
+}
+
+fn abbreviate_paths() -> bool {
+    ABBREVIATE_PATHS.load(Ordering::Relaxed)
+}
+
+/// What to do with a tag that isn't a known color, style, or semantic tag,
+/// such as the link in a markdown link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownTags {
+    /// Print the whole `[text](tag)` as it is written
+    Keep,
+    /// Print just the text, in the surrounding color, with a warning on
+    /// stderr when `debug_output` is on
+    Strip,
+}
+
+static UNKNOWN_TAGS: Mutex<UnknownTags> = Mutex::new(UnknownTags::Keep);
+
+/// Set what to do with unknown tags, which are kept as written by default.
+/// Use `try_cformat` to treat them as errors instead.
+pub fn set_unknown_tags(unknown_tags: UnknownTags) {
+    if let Ok(mut guard) = UNKNOWN_TAGS.lock() {
+        *guard = unknown_tags;
+    }
+}
+
+fn unknown_tags() -> UnknownTags {
+    UNKNOWN_TAGS
+        .lock()
+        .map_or(UnknownTags::Keep, |guard| *guard)
+}
+
+/// The error from `try_cformat` for a tag it doesn't know
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTag {
+    pub tag: String,
+}
+
+impl std::fmt::Display for UnknownTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown tag '{}'", self.tag)
+    }
+}
+
+impl std::error::Error for UnknownTag {}
+
+/// Returns the string with the ANSI color codes that `cprint_imp` would
+/// print, for building up output to print or log elsewhere.
+pub fn cformat(color: &str, s: &str) -> String {
+    format_colored(color, s, color_mode())
+}
+
+/// Returns the string as `cformat` does, or an error naming the first tag
+/// that isn't a known color, style, or semantic tag, to catch typos in tags
+/// rather than printing them.
+pub fn try_cformat(color: &str, s: &str) -> Result<String, UnknownTag> {
+    match find_unknown_tag(s) {
+        Some(tag) => Err(UnknownTag { tag }),
+        None => Ok(cformat(color, s)),
+    }
+}
+
+/// Returns the string as `cformat` would, but as plain text without any
+/// color codes. Semantic tags like `number` still format their text.
+pub fn cformat_plain(s: &str) -> String {
+    format_plain(s, unknown_tags())
+}
+
+/// Writes the string to any writer, e.g. a file or a buffer in tests, with
+/// colors exactly when `cprint_imp` would use them (see `color_enabled`), so
+/// the two never disagree.
+pub fn cprint_to<W: Write>(w: &mut W, color: &str, s: &str) -> std::io::Result<()> {
+    write_colored(w, color, s, color_enabled())
+}
+
 pub fn cprint_imp(color: &str, s: &str) {
+    let _ = cprint_to(&mut std::io::stdout(), color, s);
+}
+
+pub fn cprintln_imp(color: &str, s: &str) {
+    cprint_imp(color, s);
+    println!();
+}
+
+/// Like `cprint_imp`, but to stderr, with colors if stderr is a terminal
+pub fn ecprint_imp(color: &str, s: &str) {
+    let mut stderr = std::io::stderr();
+    let colored = color_enabled_for(stderr.is_terminal());
+    let _ = write_colored(&mut stderr, color, s, colored);
+}
+
+pub fn ecprintln_imp(color: &str, s: &str) {
+    ecprint_imp(color, s);
+    eprintln!();
+}
+
+//===========================================================================//
+// Implementation internals
+//===========================================================================//
+
+/// Write the string with color codes, or as plain text if not `colored`
+fn write_colored<W: Write>(w: &mut W, color: &str, s: &str, colored: bool) -> std::io::Result<()> {
+    if colored {
+        w.write_all(cformat(color, s).as_bytes())
+    } else {
+        w.write_all(cformat_plain(s).as_bytes())
+    }
+}
+
+fn format_colored(color: &str, s: &str, mode: ColorMode) -> String {
+    format_colored_with(color, s, mode, unknown_tags())
+}
+
+fn format_colored_with(color: &str, s: &str, mode: ColorMode, unknown_tags: UnknownTags) -> String {
     let base_color_rgb = match parse_color(color) {
         Some(rgb) => rgb,
-        None => RGB::gray(),
+        None => Rgb::gray(),
+    };
+
+    let base = base_color_rgb.to_ansi(mode);
+    let mut out = base.clone();
+    let render = Render {
+        mode: Some(mode),
+        unknown_tags,
+    };
+    push_fragments(&mut out, s, "", render, &base);
+    out.push_str(RESET);
+    out
+}
+
+fn format_plain(s: &str, unknown_tags: UnknownTags) -> String {
+    let mut out = String::new();
+    let render = Render {
+        mode: None,
+        unknown_tags,
     };
+    push_fragments(&mut out, s, "", render, "");
+    out
+}
+
+/// How `push_fragments` writes text: with color codes for a `mode` or as
+/// plain text without one, and what to do with unknown tags
+#[derive(Debug, Clone, Copy)]
+struct Render {
+    mode: Option<ColorMode>,
+    unknown_tags: UnknownTags,
+}
 
-    print!("{}", base_color_rgb.to_ansi());
+/// Formats the fragments of `s`, the text of a span tagged with `tag`, onto
+/// `out`. Tagged fragments are formatted recursively so tags can nest: an
+/// inner tag applies on top of the outer one for its span, and `restore`, the
+/// codes that set up the outer span, brings the outer formatting back after.
+fn push_fragments(out: &mut String, s: &str, tag: &str, render: Render, restore: &str) {
     for fragment in parse_text(s) {
         if fragment.tag.is_empty() {
-            print!("{}", fragment.text);
-        } else {
-            let text = format_text(fragment.text, &fragment.tag);
-
-            match parse_color(&fragment.tag) {
-                Some(rgb) => {
-                    print!("{}{}{}", rgb.to_ansi(), text, base_color_rgb.to_ansi());
+            out.push_str(&format_tag_text(fragment.text, tag, render.mode));
+            continue;
+        }
+        match (parse_style(&fragment.tag), render.mode) {
+            (Some(style), Some(mode)) => {
+                let ansi = style.to_ansi(mode);
+                out.push_str(&ansi);
+                if let Some((start, end)) = style.gradient {
+                    // Nested tags can't change the color mid-gradient, so
+                    // the text is colored one character at a time as plain
+                    let plain = Render {
+                        mode: None,
+                        ..render
+                    };
+                    let mut text = String::new();
+                    push_fragments(&mut text, &fragment.text, &fragment.tag, plain, "");
+                    let steps = text.chars().count().saturating_sub(1).max(1);
+                    for (i, c) in text.chars().enumerate() {
+                        let rgb = start.blend(end, i as f32 / steps as f32);
+                        let _ = write!(out, "{}{}", rgb.to_ansi(mode), c);
+                    }
+                } else {
+                    let inner_restore = format!("{}{}", restore, ansi);
+                    push_fragments(out, &fragment.text, &fragment.tag, render, &inner_restore);
                 }
-                None => {
-                    print!("[{}]({})", text, fragment.tag);
+                // Styles and backgrounds outlive a color change, so clear
+                // them before going back to the outer color
+                if style.needs_reset() {
+                    out.push_str(RESET);
                 }
+                out.push_str(restore);
             }
+            (Some(_), None) => {
+                push_fragments(out, &fragment.text, &fragment.tag, render, restore);
+            }
+            (None, _) => match render.unknown_tags {
+                UnknownTags::Keep => {
+                    out.push('[');
+                    push_fragments(out, &fragment.text, &fragment.tag, render, restore);
+                    let _ = write!(out, "]({})", fragment.tag);
+                }
+                UnknownTags::Strip => {
+                    // To stderr rather than through `debugln!`, so the warning
+                    // can't end up in output that is being formatted for stdout
+                    if debug_output() {
+                        eprintln!("warning: dropping unknown tag ({})", fragment.tag);
+                    }
+                    push_fragments(out, &fragment.text, "", render, restore);
+                }
+            },
         }
     }
-    print!("{}", RESET);
 }
 
-pub fn cprintln_imp(color: &str, s: &str) {
-    cprint_imp(color, s);
-    println!();
+/// The first tag in `s`, including nested ones, that isn't a known color,
+/// style, or semantic tag
+fn find_unknown_tag(s: &str) -> Option<String> {
+    parse_text(s)
+        .into_iter()
+        .filter(|fragment| !fragment.tag.is_empty())
+        .find_map(|fragment| match parse_style(&fragment.tag) {
+            Some(_) => find_unknown_tag(&fragment.text),
+            None => Some(fragment.tag),
+        })
 }
 
-//===========================================================================//
-// Implementation internals
-//===========================================================================//
-
 /// Given a string, parses out anything matching the markdown-like
 /// syntax of [some text](tag) and returns a vector of Fragments.
 ///
@@ -107,14 +539,14 @@ fn parse_text(s: &str) -> Vec<Fragment> {
     let len = chars.len();
 
     while current_pos < len {
-        if let Some(mut open_bracket_pos) = chars[current_pos..].iter().position(|&c| c == '[') {
-            open_bracket_pos += current_pos; // Make absolute
-
+        if let Some(open_bracket_pos) =
+            (current_pos..len).find(|&pos| chars[pos] == '[' && !is_escaped(&chars, pos))
+        {
             // Add text before the '['
             if open_bracket_pos > current_pos {
                 fragments.push(Fragment {
                     tag: "".to_string(),
-                    text: chars[current_pos..open_bracket_pos].iter().collect(),
+                    text: unescape(&chars[current_pos..open_bracket_pos]),
                 });
             }
 
@@ -126,6 +558,7 @@ fn parse_text(s: &str) -> Vec<Fragment> {
 
             while scan_pos < len {
                 match chars[scan_pos] {
+                    _ if is_escaped(&chars, scan_pos) => {}
                     '[' => bracket_nesting_level += 1,
                     ']' => {
                         bracket_nesting_level -= 1;
@@ -197,147 +630,500 @@ fn parse_text(s: &str) -> Vec<Fragment> {
     if current_pos < len {
         fragments.push(Fragment {
             tag: "".to_string(),
-            text: chars[current_pos..].iter().collect(),
+            text: unescape(&chars[current_pos..]),
         });
     }
     if fragments.is_empty() && !s.is_empty() {
         fragments.push(Fragment {
             tag: "".to_string(),
-            text: s.to_string(),
+            text: unescape(&chars),
         });
     }
 
     fragments
 }
 
+/// Whether the character at `pos` is escaped with a backslash, as in `\[`
+fn is_escaped(chars: &[char], pos: usize) -> bool {
+    pos > 0 && chars[pos - 1] == '\\'
+}
+
+/// Collects the characters into a string, dropping the backslash from any
+/// escaped `\[` or `\]`
+fn unescape(chars: &[char]) -> String {
+    let mut out = String::with_capacity(chars.len());
+    for (pos, &c) in chars.iter().enumerate() {
+        let escapes_bracket = matches!(chars.get(pos + 1), Some('[' | ']'));
+        if !(c == '\\' && escapes_bracket) {
+            out.push(c);
+        }
+    }
+    out
+}
+
 struct Fragment {
     tag: String,
     text: String,
 }
 
-#[derive(Debug, Clone, Copy)]
-struct RGB {
-    r: u8,
-    g: u8,
-    b: u8,
+/// A 24-bit color, as used by the tag syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
-impl RGB {
+impl Rgb {
     fn gray() -> Self {
-        RGB {
+        Rgb {
             r: 128,
             g: 128,
             b: 128,
         }
     }
 
-    fn to_ansi(&self) -> String {
-        format!("\x1b[38;2;{};{};{}m", self.r, self.g, self.b)
+    /// Parses a hex color like `#4CF` or `#44ccff`, with or without the `#`
+    pub fn from_hex(hex: &str) -> Option<Rgb> {
+        match hex.strip_prefix('#').unwrap_or(hex).len() {
+            3 | 6 => Rgb::from_hex_with_alpha(hex).map(|(rgb, _)| rgb),
+            _ => None,
+        }
     }
-}
 
-/// Semantic text formatting (not just color)
-fn format_text(s: String, tag: &str) -> String {
-    match tag {
-        "number" => {
-            if let Ok(num) = s.parse::<i64>() {
-                let num_str = num.abs().to_string();
-                let chars: Vec<char> = num_str.chars().rev().collect();
-                let mut with_commas = String::new();
-                for (i, c) in chars.iter().enumerate() {
-                    if i > 0 && i % 3 == 0 {
-                        with_commas.push(',');
-                    }
-                    with_commas.push(*c);
-                }
-                let formatted: String = with_commas.chars().rev().collect();
-                if num < 0 {
-                    format!("-{}", formatted)
+    /// Parses a hex color that may also have an alpha, like `#4CF8` or
+    /// `#44ccff80`, into the color and its alpha (255 when it has none)
+    pub fn from_hex_with_alpha(hex: &str) -> Option<(Rgb, u8)> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+        let short = |i: usize| channel(&hex[i..i + 1].repeat(2));
+        let long = |i: usize| channel(&hex[2 * i..2 * i + 2]);
+        let (rgb, alpha) = match hex.len() {
+            3 | 4 => (
+                Rgb {
+                    r: short(0)?,
+                    g: short(1)?,
+                    b: short(2)?,
+                },
+                if hex.len() == 4 { short(3)? } else { 255 },
+            ),
+            6 | 8 => (
+                Rgb {
+                    r: long(0)?,
+                    g: long(1)?,
+                    b: long(2)?,
+                },
+                if hex.len() == 8 { long(3)? } else { 255 },
+            ),
+            _ => return None,
+        };
+        Some((rgb, alpha))
+    }
+
+    /// The color as a lowercase `#rrggbb` string
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Converts a hue in degrees and a saturation and lightness from 0 to 1
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Rgb {
+        let s = s.clamp(0.0, 1.0);
+        let l = l.clamp(0.0, 1.0);
+        let h = h.rem_euclid(360.0) / 60.0;
+
+        let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = l - chroma / 2.0;
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Rgb {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+
+    /// The color `amount` (from 0 to 1) of the way from this color to `other`
+    pub fn blend(self, other: Rgb, amount: f32) -> Rgb {
+        let amount = amount.clamp(0.0, 1.0);
+        let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+        Rgb {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+        }
+    }
+
+    /// The escape code that sets this as the text color in the given mode
+    pub fn to_ansi(self, mode: ColorMode) -> String {
+        self.to_sgr(mode, false)
+    }
+
+    fn to_ansi_bg(self, mode: ColorMode) -> String {
+        self.to_sgr(mode, true)
+    }
+
+    fn to_sgr(self, mode: ColorMode, background: bool) -> String {
+        let layer = if background { 48 } else { 38 };
+        match mode {
+            ColorMode::TrueColor => {
+                format!("\x1b[{};2;{};{};{}m", layer, self.r, self.g, self.b)
+            }
+            ColorMode::Ansi256 => format!("\x1b[{};5;{}m", layer, self.to_ansi256()),
+            ColorMode::Ansi16 => {
+                let index = self.to_ansi16();
+                let code = if index < 8 {
+                    30 + index
                 } else {
-                    formatted
-                }
-            } else {
-                s
+                    90 + index - 8
+                };
+                format!("\x1b[{}m", if background { code + 10 } else { code })
             }
         }
-        "filename" | "filepath" => {
-            let prefix_rgb = parse_hex("#ed552b").unwrap().to_ansi();
-            let text_rgb = parse_color(tag).unwrap().to_ansi();
+    }
 
-            let cwd = match std::env::current_dir() {
-                Ok(path) => path.to_string_lossy().to_string(),
-                Err(_) => "".to_string(),
-            };
-            if !cwd.is_empty() && s != cwd && s.starts_with(&cwd) {
-                return format!("{}.{}{}", prefix_rgb, text_rgb, &s[cwd.len()..]);
+    /// The nearest color in the xterm 256-color palette: either one of the
+    /// 6x6x6 color cube (16-231) or one of the grays (232-255)
+    fn to_ansi256(self) -> u8 {
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let cube_index = |v: u8| match v {
+            0..48 => 0,
+            48..115 => 1,
+            _ => (v - 35) / 40,
+        };
+        let (r, g, b) = (cube_index(self.r), cube_index(self.g), cube_index(self.b));
+        let cube = Rgb {
+            r: LEVELS[r as usize],
+            g: LEVELS[g as usize],
+            b: LEVELS[b as usize],
+        };
+
+        let average = (self.r as u32 + self.g as u32 + self.b as u32) / 3;
+        let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+        let level = 8 + 10 * gray_index;
+        let gray = Rgb {
+            r: level,
+            g: level,
+            b: level,
+        };
+
+        if self.distance(gray) < self.distance(cube) {
+            232 + gray_index
+        } else {
+            16 + 36 * r + 6 * g + b
+        }
+    }
+
+    /// The index of the nearest of the 16 standard ANSI colors, with 0-7 the
+    /// normal colors and 8-15 their bright variants
+    fn to_ansi16(self) -> u8 {
+        const PALETTE: [(u8, u8, u8); 16] = [
+            (0, 0, 0),
+            (128, 0, 0),
+            (0, 128, 0),
+            (128, 128, 0),
+            (0, 0, 128),
+            (128, 0, 128),
+            (0, 128, 128),
+            (192, 192, 192),
+            (128, 128, 128),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (0, 0, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        (0..16u8)
+            .min_by_key(|&i| {
+                let (r, g, b) = PALETTE[i as usize];
+                self.distance(Rgb { r, g, b })
+            })
+            .unwrap_or(0)
+    }
+
+    fn distance(self, other: Rgb) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(self.r, other.r) + d(self.g, other.g) + d(self.b, other.b)
+    }
+}
+
+/// The colors and text styles a tag applies
+#[derive(Debug, Clone, Copy, Default)]
+struct Style {
+    color: Option<Rgb>,
+    background: Option<Rgb>,
+    gradient: Option<(Rgb, Rgb)>,
+    bold: bool,
+    faint: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    fn needs_reset(self) -> bool {
+        self.bold || self.faint || self.italic || self.underline || self.background.is_some()
+    }
+
+    fn to_ansi(self, mode: ColorMode) -> String {
+        let mut s = String::new();
+        if self.bold {
+            s.push_str("\x1b[1m");
+        }
+        if self.faint {
+            s.push_str("\x1b[2m");
+        }
+        if self.italic {
+            s.push_str("\x1b[3m");
+        }
+        if self.underline {
+            s.push_str("\x1b[4m");
+        }
+        if let Some(rgb) = self.color {
+            s.push_str(&rgb.to_ansi(mode));
+        }
+        if let Some(rgb) = self.background {
+            s.push_str(&rgb.to_ansi_bg(mode));
+        }
+        s
+    }
+}
+
+/// Parses a tag such as `red,bold,bg:#333` or `gradient:#39F..#7AF` into a style, or `None` if any
+/// part is neither a color nor a known style
+fn parse_style(tag: &str) -> Option<Style> {
+    let mut style = Style::default();
+    let mut dim = None;
+    for part in tag.split(',') {
+        match part.trim() {
+            "bold" => style.bold = true,
+            "italic" => style.italic = true,
+            "underline" => style.underline = true,
+            part if part.starts_with("bg:") => {
+                style.background = Some(parse_color(part["bg:".len()..].trim())?)
             }
-            if let Ok(home) = std::env::var("HOME") {
-                if s.starts_with(&home) {
-                    return format!("{}~{}{}", prefix_rgb, text_rgb, &s[home.len()..]);
-                }
+            part if part.starts_with("number:") => {
+                number_separators(&part["number:".len()..])?;
+                style.color = Some(parse_color("number")?)
+            }
+            part if part.starts_with("percent:") => {
+                part["percent:".len()..].parse::<usize>().ok()?;
+                style.color = Some(parse_color("percent")?)
+            }
+            part if part.starts_with("gradient:") => {
+                let (start, end) = part["gradient:".len()..].split_once("..")?;
+                style.gradient = Some((parse_color(start.trim())?, parse_color(end.trim())?));
+            }
+            part if part.starts_with("dim:") => {
+                let percent: u8 = part["dim:".len()..].parse().ok()?;
+                dim = Some(percent.min(100));
             }
-            s
+            part if custom_formatter(part).is_some() => {}
+            part => style.color = Some(parse_color(part)?),
         }
-        _ => s,
     }
+
+    // Dimming blends the tag's color toward the background, and without a
+    // color to blend falls back to the terminal's own faint style
+    match (dim, style.color) {
+        (Some(percent), Some(color)) => {
+            style.color = Some(color.blend(dim_background(), percent as f32 / 100.0));
+        }
+        (Some(_), None) => style.faint = true,
+        (None, _) => {}
+    }
+    Some(style)
 }
 
-fn parse_color(color: &str) -> Option<RGB> {
-    // Check custom colors first (dynamic, with aliasing)
-    let resolved_color = {
-        let colors = ensure_custom_colors();
-        if let Ok(colors_guard) = colors.lock() {
-            colors_guard
-                .get(color)
-                .cloned()
-                .unwrap_or_else(|| color.to_string())
-        } else {
-            color.to_string()
+/// Applies the semantic formatting of each part of a comma-separated tag
+fn format_tag_text(s: String, tag: &str, mode: Option<ColorMode>) -> String {
+    tag.split(',')
+        .fold(s, |s, part| format_text(s, part.trim(), mode))
+}
+
+/// Semantic text formatting (not just color), with the formatting itself
+/// colored unless `mode` is `None`
+fn format_text(s: String, tag: &str, mode: Option<ColorMode>) -> String {
+    if let Some(format) = custom_formatter(tag) {
+        return format(&s);
+    }
+    match tag {
+        "number" => format_number(s, "en"),
+        tag if tag.starts_with("number:") => format_number(s, &tag["number:".len()..]),
+        "duration" => match s.parse::<f64>() {
+            Ok(secs) if is_number(&s) => to_pretty_duration(secs),
+            _ => s,
+        },
+        "percent" => format_percent(s, 1),
+        tag if tag.starts_with("percent:") => match tag["percent:".len()..].parse() {
+            Ok(decimals) => format_percent(s, decimals),
+            Err(_) => s,
+        },
+        "filename" | "filepath" => {
+            if !abbreviate_paths() {
+                return s;
+            }
+            let cwd = std::env::current_dir().ok();
+            let home = std::env::home_dir();
+            let Some((prefix, rest)) = abbreviate_path(
+                &s,
+                cwd.as_deref().map(Path::to_string_lossy).as_deref(),
+                home.as_deref().map(Path::to_string_lossy).as_deref(),
+            ) else {
+                return s;
+            };
+
+            let (prefix_rgb, text_rgb) = match mode {
+                Some(mode) => (
+                    parse_color("filename_prefix")
+                        .unwrap_or_else(|| Rgb::from_hex("#ed552b").unwrap())
+                        .to_ansi(mode),
+                    parse_color(tag).unwrap().to_ansi(mode),
+                ),
+                None => (String::new(), String::new()),
+            };
+            format!("{}{}{}{}", prefix_rgb, prefix, text_rgb, rest)
         }
-    };
-    let hex = if let Some(hex) = html_named_color(&resolved_color) {
-        hex
-    } else if let Some(hex) = snowfall_color(&resolved_color) {
-        hex
-    } else {
-        resolved_color.as_str()
-    };
-    parse_hex(hex)
+        _ => s,
+    }
 }
 
-fn parse_hex(hex: &str) -> Option<RGB> {
-    let hex = if hex.len() == 7 && hex.starts_with('#') {
-        &hex[1..]
-    } else if hex.len() == 4 && hex.starts_with('#') {
-        &hex[1..]
-    } else {
-        hex
+/// Groups the digits of a number for the locale (`en`, `de`, or `fr`),
+/// leaving anything that isn't a number unchanged
+fn format_number(s: String, locale: &str) -> String {
+    let Some((group, decimal)) = number_separators(locale) else {
+        return s;
     };
+    if !is_number(&s) {
+        return s;
+    }
+    to_comma_string_with(&s, group, decimal)
+}
 
-    let rgb = match hex.len() {
-        3 => {
-            let r = u8::from_str_radix(&hex[0..1].repeat(2), 16).unwrap_or(0);
-            let g = u8::from_str_radix(&hex[1..2].repeat(2), 16).unwrap_or(0);
-            let b = u8::from_str_radix(&hex[2..3].repeat(2), 16).unwrap_or(0);
-            RGB { r, g, b }
-        }
-        6 => {
-            let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0);
-            let g = u8::from_str_radix(&hex[2..4], 16).unwrap_or(0);
-            let b = u8::from_str_radix(&hex[4..6], 16).unwrap_or(0);
-            RGB { r, g, b }
-        }
-        _ => {
+/// Shows a fraction as a percentage (e.g. 0.873 -> 87.3%), leaving anything
+/// that isn't a number unchanged
+fn format_percent(s: String, decimals: usize) -> String {
+    if !is_number(&s) {
+        return s;
+    }
+    match s.parse::<f64>() {
+        Ok(fraction) => format!("{:.*}%", decimals, fraction * 100.0),
+        Err(_) => s,
+    }
+}
+
+/// Splits a path inside the working directory into `.` and the rest, or one
+/// inside the home directory into `~` and the rest
+fn abbreviate_path<'a>(
+    path: &'a str,
+    cwd: Option<&str>,
+    home: Option<&str>,
+) -> Option<(&'static str, &'a str)> {
+    if let Some(rest) = cwd.and_then(|cwd| strip_dir_prefix(path, cwd))
+        && !rest.is_empty()
+    {
+        return Some((".", rest));
+    }
+    let rest = home.and_then(|home| strip_dir_prefix(path, home))?;
+    Some(("~", rest))
+}
+
+/// The rest of the path after `dir`, if the path is `dir` or inside it. On
+/// Windows, `/` and `\` count as the same separator.
+fn strip_dir_prefix<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    let is_sep = |c: char| c == '/' || (cfg!(windows) && c == '\\');
+    let dir = dir.trim_end_matches(is_sep);
+    if dir.is_empty() {
+        return None;
+    }
+
+    let mut path_chars = path.chars();
+    for d in dir.chars() {
+        let p = path_chars.next()?;
+        if p != d && !(is_sep(p) && is_sep(d)) {
             return None;
         }
+    }
+    let rest = path_chars.as_str();
+    (rest.is_empty() || rest.starts_with(is_sep)).then_some(rest)
+}
+
+/// Whether the string is a plain decimal number like `-1234.5`, of any size
+fn is_number(s: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let s = s.strip_prefix('-').unwrap_or(s);
+    match s.split_once('.') {
+        Some((int_part, frac_part)) => digits(int_part) && digits(frac_part),
+        None => digits(s),
+    }
+}
+
+/// The digit group and decimal separators of a `number:` locale
+fn number_separators(locale: &str) -> Option<(char, char)> {
+    match locale {
+        "en" => Some((',', '.')),
+        "de" => Some(('.', ',')),
+        "fr" => Some((' ', ',')),
+        _ => None,
+    }
+}
+
+/// Parses a color, blending a translucent one (like `#f008`) over the
+/// `dim_background` color
+pub(crate) fn parse_color(color: &str) -> Option<Rgb> {
+    let (rgb, alpha) = parse_color_with_alpha(color)?;
+    if alpha == 255 {
+        return Some(rgb);
+    }
+    Some(rgb.blend(dim_background(), 1.0 - alpha as f32 / 255.0))
+}
+
+/// The color that `dim:` and translucent colors blend toward, ignoring any
+/// alpha of its own
+fn dim_background() -> Rgb {
+    parse_color_with_alpha("dim_background").map_or(Rgb { r: 0, g: 0, b: 0 }, |(rgb, _)| rgb)
+}
+
+fn parse_color_with_alpha(color: &str) -> Option<(Rgb, u8)> {
+    // Check custom colors first (dynamic, with aliasing). A custom color can
+    // name another custom color, so follow a few hops in case of a chain,
+    // giving up rather than looping on a cycle.
+    let mut resolved_color = color.to_string();
+    if let Ok(colors_guard) = ensure_custom_colors().lock() {
+        for _ in 0..8 {
+            match colors_guard.get(&resolved_color) {
+                Some(next) if *next != resolved_color => resolved_color = next.clone(),
+                _ => break,
+            }
+        }
+    }
+    // Named colors match regardless of case, e.g. `GoldenRod`
+    let name = resolved_color.to_ascii_lowercase();
+    let hex = if let Some(hex) = html_named_color(&name) {
+        hex
+    } else if let Some(hex) = snowfall_color(&name) {
+        hex
+    } else {
+        resolved_color.as_str()
     };
-    Some(rgb)
+    Rgb::from_hex_with_alpha(hex)
 }
 
 fn snowfall_color(name: &str) -> Option<&'static str> {
     match name {
         "filename" | "filepath" => Some("#f7cd43"),
-        "number" | "digits" => Some("#556fed"),
+        "number" | "digits" | "percent" | "duration" => Some("#556fed"),
         _ => None,
     }
 }
@@ -495,3 +1281,520 @@ fn html_named_color(name: &str) -> Option<&'static str> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{MutexGuard, PoisonError};
+
+    /// Held by tests that depend on the process-wide color mode
+    static COLOR_MODE_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Forces a color mode until dropped, when detection is restored (also if
+    /// the test panics). Tests that force one are serialized on
+    /// `COLOR_MODE_LOCK`, so they can't see each other's mode.
+    struct ForcedColorMode {
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl ForcedColorMode {
+        fn new(mode: ColorMode) -> Self {
+            let lock = COLOR_MODE_LOCK
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            set_color_mode(Some(mode));
+            Self { _lock: lock }
+        }
+    }
+
+    impl Drop for ForcedColorMode {
+        fn drop(&mut self) {
+            set_color_mode(None);
+        }
+    }
+
+    #[test]
+    fn test_cformat_formats_semantic_tags() {
+        let blue = "\x1b[38;2;85;111;237m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored("nope", "[1234567](number) files", ColorMode::TrueColor),
+            format!("{gray}{blue}1,234,567{gray} files{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_color_enabled_with() {
+        let set = Some(std::ffi::OsStr::new("1"));
+        let zero = Some(std::ffi::OsStr::new("0"));
+        assert!(color_enabled_with(None, None, true));
+        assert!(!color_enabled_with(None, None, false));
+        assert!(color_enabled_with(None, set, false));
+        assert!(!color_enabled_with(None, zero, false));
+        assert!(!color_enabled_with(set, None, true));
+        // NO_COLOR wins over CLICOLOR_FORCE
+        assert!(!color_enabled_with(set, set, true));
+        assert!(!color_enabled_with(set, set, false));
+    }
+
+    #[test]
+    fn test_cformat_plain_has_no_color_codes() {
+        assert_eq!(
+            cformat_plain("[1234567](number) files in [dir](#f00) [unknown](nope)"),
+            "1,234,567 files in dir [unknown](nope)"
+        );
+    }
+
+    #[test]
+    fn test_cformat_formats_decimals_and_negatives() {
+        assert_eq!(cformat_plain("[1234567.89](number)"), "1,234,567.89");
+        assert_eq!(cformat_plain("[-1234567](number)"), "-1,234,567");
+        assert_eq!(cformat_plain("[-0.5](number)"), "-0.5");
+        assert_eq!(
+            cformat_plain("[123456789012345678901234](number)"),
+            "123,456,789,012,345,678,901,234"
+        );
+        for s in ["abc", "12a", "1.2.3", "1.", ".5", "-", "1,000", ""] {
+            assert_eq!(cformat_plain(&format!("[{s}](number)")), s);
+        }
+    }
+
+    #[test]
+    fn test_cformat_formats_percents() {
+        assert_eq!(cformat_plain("[0.873](percent)"), "87.3%");
+        assert_eq!(cformat_plain("[0.87349](percent:2)"), "87.35%");
+        assert_eq!(cformat_plain("[0.996](percent:0)"), "100%");
+        assert_eq!(cformat_plain("[1.5](percent)"), "150.0%");
+        assert_eq!(cformat_plain("[-0.25](percent)"), "-25.0%");
+        assert_eq!(cformat_plain("[0](percent)"), "0.0%");
+        assert_eq!(cformat_plain("[n/a](percent)"), "n/a");
+        assert_eq!(cformat_plain("[0.5](percent:x)"), "[0.5](percent:x)");
+
+        let blue = "\x1b[38;2;85;111;237m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored("nope", "[0.5](percent:0)", ColorMode::TrueColor),
+            format!("{gray}{blue}50%{gray}{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_cformat_formats_durations() {
+        assert_eq!(cformat_plain("[0.26](duration)"), "0.3s");
+        assert_eq!(cformat_plain("[83.4](duration)"), "1m 23.4s");
+        assert_eq!(cformat_plain("[7520](duration)"), "2h 05m");
+        assert_eq!(cformat_plain("[soon](duration)"), "soon");
+        assert_eq!(cformat_plain("[([1.500](duration,#666))](#666)"), "(1.5s)");
+    }
+
+    #[test]
+    fn test_cformat_groups_numbers_by_locale() {
+        assert_eq!(
+            cformat_plain(
+                "[1000000](number) [1000000](number:en) [1000000](number:de) [-1000000](number:fr)"
+            ),
+            "1,000,000 1,000,000 1.000.000 -1 000 000"
+        );
+        assert_eq!(cformat_plain("[12](number:de)"), "12");
+        assert_eq!(cformat_plain("[-1234.5](number:de)"), "-1.234,5");
+        assert_eq!(cformat_plain("[1000](number:xx)"), "[1000](number:xx)");
+
+        let blue = "\x1b[38;2;85;111;237m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored("nope", "[1234](number:de)", ColorMode::TrueColor),
+            format!("{gray}{blue}1.234{gray}{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_abbreviate_path_at_directory_boundaries() {
+        let cwd = Some("/home/me/src");
+        let home = Some("/home/me");
+        assert_eq!(
+            abbreviate_path("/home/me/src/main.rs", cwd, home),
+            Some((".", "/main.rs"))
+        );
+        assert_eq!(
+            abbreviate_path("/home/me/src", cwd, home),
+            Some(("~", "/src"))
+        );
+        assert_eq!(
+            abbreviate_path("/home/me/docs", cwd, home),
+            Some(("~", "/docs"))
+        );
+        assert_eq!(abbreviate_path("/home/me", cwd, home), Some(("~", "")));
+        assert_eq!(
+            abbreviate_path("/home/me/", None, Some("/home/me/")),
+            Some(("~", "/"))
+        );
+        assert_eq!(abbreviate_path("/home/meow/x", None, home), None);
+        assert_eq!(abbreviate_path("/home/me/src2/x", cwd, None), None);
+        assert_eq!(abbreviate_path("/tmp/x", None, Some("/")), None);
+        assert_eq!(abbreviate_path("/tmp/x", None, None), None);
+        assert_eq!(
+            strip_dir_prefix(r"C:\Users\me/x", r"C:\Users\me"),
+            Some("/x")
+        );
+        assert_eq!(
+            strip_dir_prefix("C:/Users/me/x", r"C:\Users\me"),
+            if cfg!(windows) { Some("/x") } else { None }
+        );
+    }
+
+    #[test]
+    fn test_cformat_applies_styles() {
+        let red = "\x1b[38;2;255;0;0m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored(
+                "nope",
+                "[x](red,bold,underline) [y](italic)",
+                ColorMode::TrueColor
+            ),
+            format!("{gray}\x1b[1m\x1b[4m{red}x{RESET}{gray} \x1b[3my{RESET}{gray}{RESET}")
+        );
+        assert_eq!(
+            cformat_plain("[1234](number,bold) [x](red,blink)"),
+            "1,234 [x](red,blink)"
+        );
+    }
+
+    #[test]
+    fn test_cformat_applies_backgrounds() {
+        let white = "\x1b[38;2;255;255;255m";
+        let maroon_bg = "\x1b[48;2;128;0;0m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored(
+                "nope",
+                "[x](white,bg:#800000) [y](bg:maroon)",
+                ColorMode::TrueColor
+            ),
+            format!("{gray}{white}{maroon_bg}x{RESET}{gray} {maroon_bg}y{RESET}{gray}{RESET}")
+        );
+        assert_eq!(cformat_plain("[ PASS ](bg:green)"), " PASS ");
+        assert_eq!(cformat_plain("[x](bg:nope)"), "[x](bg:nope)");
+    }
+
+    #[test]
+    fn test_unknown_tags_can_be_stripped_or_rejected() {
+        let s = "see [the docs](https://example.com) and [x [y](red)](nope)";
+        assert_eq!(
+            format_plain(s, UnknownTags::Keep),
+            s.replace("[y](red)", "y")
+        );
+        assert_eq!(format_plain(s, UnknownTags::Strip), "see the docs and x y");
+
+        let red = "\x1b[38;2;255;0;0m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored_with(
+                "nope",
+                "[a](nope) [b [c](red)](nope)",
+                ColorMode::TrueColor,
+                UnknownTags::Strip
+            ),
+            format!("{gray}a b {red}c{gray}{RESET}")
+        );
+
+        assert_eq!(
+            try_cformat("#fff", "[ok](red) [a [b](blu)](bold)"),
+            Err(UnknownTag {
+                tag: "blu".to_string()
+            })
+        );
+        assert!(
+            try_cformat(
+                "#fff",
+                "[ok](red) [a [1](number)](bold) [x](gradient:red..blue)"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_cformat_nests_tags() {
+        let red = "\x1b[38;2;255;0;0m";
+        let lime = "\x1b[38;2;0;255;0m";
+        let blue = "\x1b[38;2;0;0;255m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored(
+                "nope",
+                "[a [b [c](red) d](lime) e](blue)!",
+                ColorMode::TrueColor
+            ),
+            format!("{gray}{blue}a {lime}b {red}c{gray}{blue}{lime} d{gray}{blue} e{gray}!{RESET}")
+        );
+        assert_eq!(
+            format_colored("nope", "[a [b](red) c](blue,bold)", ColorMode::TrueColor),
+            format!("{gray}\x1b[1m{blue}a {red}b{gray}\x1b[1m{blue} c{RESET}{gray}{RESET}")
+        );
+        assert_eq!(
+            cformat_plain("[a [b [1234](number) d](red) e](blue) [f [g](red)](nope)"),
+            "a b 1,234 d e [f g](nope)"
+        );
+    }
+
+    #[test]
+    fn test_cformat_applies_gradients() {
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        let c = |r, g, b| Rgb { r, g, b }.to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored("nope", "[~~~](gradient:#000..#fff)", ColorMode::TrueColor),
+            format!(
+                "{gray}{}~{}~{}~{gray}{RESET}",
+                c(0, 0, 0),
+                c(128, 128, 128),
+                c(255, 255, 255)
+            )
+        );
+        assert_eq!(
+            format_colored("nope", "[~](gradient:red..blue,bold)", ColorMode::TrueColor),
+            format!("{gray}\x1b[1m{}~{RESET}{gray}{RESET}", c(255, 0, 0))
+        );
+        assert_eq!(cformat_plain("[a[b](red)](gradient:red..blue)"), "ab");
+        assert_eq!(
+            cformat_plain("[a](gradient:red) [b](gradient:red..nope)"),
+            "[a](gradient:red) [b](gradient:red..nope)"
+        );
+    }
+
+    #[test]
+    fn test_cformat_escapes_brackets() {
+        let red = "\x1b[38;2;255;0;0m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored("nope", r"\[a](red) [b](red)", ColorMode::TrueColor),
+            format!("{gray}[a](red) {red}b{gray}{RESET}")
+        );
+        assert_eq!(
+            cformat_plain(r"[x \] y](red) \[z\] [w \[v\](red)](blue) a\b"),
+            r"x ] y [z] w [v](red) a\b"
+        );
+        assert_eq!(
+            cformat_plain(r"[outer [inner](red) \[not\](red)](blue)"),
+            "outer inner [not](red)"
+        );
+    }
+
+    #[test]
+    fn test_cprint_add_color_validates_values() {
+        let _lock = COLOR_TABLE_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        assert_eq!(cprint_add_color("test_add_hex", "#123456"), Ok(()));
+        assert_eq!(cprint_add_color("test_add_named", "tomato"), Ok(()));
+        assert_eq!(cprint_add_color("test_add_alias", "warn"), Ok(()));
+        assert_eq!(cprint_add_color("test_add_chain", "test_add_alias"), Ok(()));
+        assert_eq!(parse_color("test_add_chain"), Rgb::from_hex("#ffea00"));
+
+        let err = cprint_add_color("test_add_typo", "#12345g").unwrap_err();
+        assert_eq!(err.value, "#12345g");
+        assert_eq!(parse_color("test_add_typo"), None);
+
+        cprint_add_color_unchecked("test_add_unchecked", "nope");
+        assert_eq!(parse_color("test_add_unchecked"), None);
+    }
+
+    #[test]
+    fn test_set_base_colors_and_reset() {
+        let _lock = COLOR_TABLE_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        cprint_add_color_unchecked("test_reset_added", "#010203");
+
+        set_base_colors(&[("test_base_a, test_base_b", "#123456"), ("text", "#fff")]);
+        assert_eq!(parse_color("test_base_b"), Rgb::from_hex("#123456"));
+        assert_eq!(parse_color(""), Rgb::from_hex("#fff"));
+        assert_eq!(parse_color("warn"), None);
+        assert_eq!(parse_color("test_reset_added"), None);
+
+        reset_colors();
+        assert_eq!(parse_color("test_base_a"), None);
+        assert_eq!(parse_color("warn"), Rgb::from_hex("#ffea00"));
+        assert_eq!(parse_color(""), Rgb::from_hex("#bbb"));
+    }
+
+    #[test]
+    fn test_rgb_hex_and_hsl() {
+        let sky = Rgb {
+            r: 0x44,
+            g: 0xcc,
+            b: 0xff,
+        };
+        assert_eq!(Rgb::from_hex("#4CF"), Some(sky));
+        assert_eq!(Rgb::from_hex("44ccff"), Some(sky));
+        assert_eq!(Rgb::from_hex("#44ccf"), None);
+        assert_eq!(Rgb::from_hex("#xyz"), None);
+        assert_eq!(sky.to_hex(), "#44ccff");
+
+        assert_eq!(Rgb::from_hsl(0.0, 1.0, 0.5).to_hex(), "#ff0000");
+        assert_eq!(Rgb::from_hsl(120.0, 1.0, 0.5).to_hex(), "#00ff00");
+        assert_eq!(Rgb::from_hsl(-120.0, 1.0, 0.5).to_hex(), "#0000ff");
+        assert_eq!(Rgb::from_hsl(39.0, 1.0, 0.5).to_hex(), "#ffa600");
+        assert_eq!(Rgb::from_hsl(200.0, 0.0, 0.5).to_hex(), "#808080");
+    }
+
+    #[test]
+    fn test_cformat_dims_colors() {
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        let c = |r, g, b| Rgb { r, g, b }.to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored(
+                "nope",
+                "[a](#fff,dim:50) [b](dim:25,blue)",
+                ColorMode::TrueColor
+            ),
+            format!(
+                "{gray}{}a{gray} {}b{gray}{RESET}",
+                c(128, 128, 128),
+                c(0, 0, 191)
+            )
+        );
+        assert_eq!(
+            format_colored("nope", "[a](dim:40)", ColorMode::TrueColor),
+            format!("{gray}\x1b[2ma{RESET}{gray}{RESET}")
+        );
+        assert_eq!(
+            cformat_plain("[a](red,dim:x) [b](red,dim:-1)"),
+            "[a](red,dim:x) [b](red,dim:-1)"
+        );
+        assert_eq!(cformat_plain("[a](red,dim:100)"), "a");
+
+        let white = Rgb::from_hex("#fff").unwrap();
+        assert_eq!(
+            white.blend(Rgb::from_hex("#000").unwrap(), 0.25).to_hex(),
+            "#bfbfbf"
+        );
+        assert_eq!(
+            white.blend(Rgb::from_hex("#000").unwrap(), 2.0).to_hex(),
+            "#000000"
+        );
+    }
+
+    #[test]
+    fn test_hex_colors_with_alpha() {
+        let sky = Rgb {
+            r: 0x44,
+            g: 0xcc,
+            b: 0xff,
+        };
+        assert_eq!(Rgb::from_hex_with_alpha("#4cf"), Some((sky, 255)));
+        assert_eq!(Rgb::from_hex_with_alpha("#4cf8"), Some((sky, 0x88)));
+        assert_eq!(Rgb::from_hex_with_alpha("44ccff"), Some((sky, 255)));
+        assert_eq!(Rgb::from_hex_with_alpha("#44ccff80"), Some((sky, 0x80)));
+        assert_eq!(Rgb::from_hex_with_alpha("#44ccff8"), None);
+        assert_eq!(Rgb::from_hex("#4cf8"), None);
+        assert_eq!(Rgb::from_hex("#44ccff80"), None);
+
+        assert_eq!(
+            parse_color("#fff0").map(Rgb::to_hex).as_deref(),
+            Some("#000000")
+        );
+        assert_eq!(
+            parse_color("#ffffff80").map(Rgb::to_hex).as_deref(),
+            Some("#808080")
+        );
+        assert_eq!(
+            parse_color("#f00f").map(Rgb::to_hex).as_deref(),
+            Some("#ff0000")
+        );
+        assert_eq!(cformat_plain("[x](#fff8,bold)"), "x");
+    }
+
+    #[test]
+    fn test_named_colors_ignore_case() {
+        assert_eq!(parse_color("Red"), Rgb::from_hex("#ff0000"));
+        assert_eq!(parse_color("GoldenRod"), Rgb::from_hex("#daa520"));
+        assert_eq!(parse_color("NUMBER"), Rgb::from_hex("#556fed"));
+        assert_eq!(parse_color("#ABCDEF"), Rgb::from_hex("#abcdef"));
+        assert_eq!(cformat_plain("[x](Tomato,bold) [y](Nope)"), "x [y](Nope)");
+    }
+
+    #[test]
+    fn test_registered_formatters() {
+        register_formatter("test_sha", |s| s.chars().take(7).collect());
+        register_formatter("test_shout", |s| s.to_uppercase());
+        assert_eq!(
+            cformat_plain("[4b825dc642cb6eb9](test_sha) [hi](test_shout,red)"),
+            "4b825dc HI"
+        );
+
+        let red = "\x1b[38;2;255;0;0m";
+        let gray = Rgb::gray().to_ansi(ColorMode::TrueColor);
+        assert_eq!(
+            format_colored(
+                "nope",
+                "[abcdefghij](test_sha) [x](test_sha,red)",
+                ColorMode::TrueColor
+            ),
+            format!("{gray}abcdefg{gray} {red}x{gray}{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_set_debug_output_overrides_env() {
+        set_debug_output(Some(true));
+        assert!(debug_output());
+        set_debug_output(Some(false));
+        assert!(!debug_output());
+        set_debug_output(None);
+        assert_eq!(
+            debug_output(),
+            std::env::var_os("SNOWFALL_DEBUG").is_some_and(|v| !v.is_empty() && v != "0")
+        );
+    }
+
+    #[test]
+    fn test_colors_fall_back_to_limited_palettes() {
+        let orange = Rgb {
+            r: 255,
+            g: 135,
+            b: 0,
+        };
+        assert_eq!(orange.to_ansi(ColorMode::Ansi256), "\x1b[38;5;208m");
+        assert_eq!(orange.to_ansi_bg(ColorMode::Ansi256), "\x1b[48;5;208m");
+        assert_eq!(Rgb::gray().to_ansi(ColorMode::Ansi256), "\x1b[38;5;244m");
+        assert_eq!(orange.to_ansi(ColorMode::Ansi16), "\x1b[93m");
+        assert_eq!(
+            Rgb::from_hex("#800000")
+                .unwrap()
+                .to_ansi_bg(ColorMode::Ansi16),
+            "\x1b[41m"
+        );
+        assert_eq!(
+            format_colored("#fff", "[x](bold,#000)", ColorMode::Ansi16),
+            format!("\x1b[97m\x1b[1m\x1b[30mx{RESET}\x1b[97m{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_cprint_to_writes_colored_fragments() {
+        let _mode = ForcedColorMode::new(ColorMode::TrueColor);
+        let s = "plain [tagged](#000) [unknown](nope)";
+        let mut out = Vec::new();
+        write_colored(&mut out, "#fff", s, true).unwrap();
+        let white = "\x1b[38;2;255;255;255m";
+        let black = "\x1b[38;2;0;0;0m";
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{white}plain {black}tagged{white} [unknown](nope){RESET}")
+        );
+
+        let mut out = Vec::new();
+        write_colored(&mut out, "#fff", s, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), cformat_plain(s));
+
+        // Colored only when cprint_imp would color stdout
+        let mut out = Vec::new();
+        cprint_to(&mut out, "#fff", s).unwrap();
+        let expected = if color_enabled() {
+            cformat("#fff", s)
+        } else {
+            cformat_plain(s)
+        };
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
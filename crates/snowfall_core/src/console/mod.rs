@@ -1,2 +1,6 @@
+mod ansi;
 mod cprint_imp;
+mod theme;
+pub use ansi::*;
 pub use cprint_imp::*;
+pub use theme::*;
@@ -0,0 +1,142 @@
+//! Loading color palettes ("themes") from TOML files into the custom color
+//! table, so tools can share a color vocabulary without recompiling.
+//!
+//! A theme is a flat table of names to colors, where a key can list several
+//! comma-separated aliases and a value can name another color:
+//!
+//! ```toml
+//! h1 = "#fff"
+//! "txt,text" = "#bbb"
+//! heading = "h1"
+//! ```
+
+use std::path::Path;
+
+use super::cprint_imp::{InvalidColor, cprint_add_color};
+
+/// The error from loading a theme
+#[derive(Debug)]
+pub enum ThemeError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    /// The file isn't valid TOML
+    Parse(toml::de::Error),
+    /// An entry's value isn't a string
+    NotAString(String),
+    /// An entry's value isn't a color
+    InvalidColor(InvalidColor),
+}
+
+impl std::fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThemeError::Io(err) => write!(f, "could not read theme: {}", err),
+            ThemeError::Parse(err) => write!(f, "could not parse theme: {}", err),
+            ThemeError::NotAString(key) => write!(f, "theme entry '{}' is not a string", key),
+            ThemeError::InvalidColor(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+/// Reads a theme file and adds its colors with `cprint_add_color`
+pub fn load_theme(path: impl AsRef<Path>) -> Result<(), ThemeError> {
+    let content = std::fs::read_to_string(path).map_err(ThemeError::Io)?;
+    load_theme_str(&content)
+}
+
+/// Adds the colors of a theme given as a TOML string with `cprint_add_color`.
+/// Entries may name colors defined anywhere else in the theme; on an error,
+/// the entries before it may already have been added.
+pub fn load_theme_str(content: &str) -> Result<(), ThemeError> {
+    let table: toml::Table = content.parse().map_err(ThemeError::Parse)?;
+
+    let mut pending = Vec::new();
+    for (key, value) in &table {
+        let value = value
+            .as_str()
+            .ok_or_else(|| ThemeError::NotAString(key.clone()))?;
+        for part in key.split(',') {
+            let name = part.trim();
+            if !name.is_empty() {
+                pending.push((name, value));
+            }
+            if name == "text" {
+                pending.push(("", value));
+            }
+        }
+    }
+
+    // An entry naming another entry only resolves once that one is added,
+    // so keep retrying until a pass adds nothing new
+    loop {
+        let before = pending.len();
+        let mut last_err = None;
+        pending.retain(|&(name, value)| match cprint_add_color(name, value) {
+            Ok(()) => false,
+            Err(err) => {
+                last_err = Some(err);
+                true
+            }
+        });
+        match last_err {
+            None => return Ok(()),
+            Some(err) if pending.len() == before => {
+                return Err(ThemeError::InvalidColor(err));
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::cprint_imp::{COLOR_TABLE_LOCK, Rgb, parse_color};
+
+    fn color_hex(name: &str) -> Option<String> {
+        parse_color(name).map(Rgb::to_hex)
+    }
+
+    #[test]
+    fn test_load_theme_adds_aliases() {
+        let _lock = COLOR_TABLE_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let path = std::env::temp_dir().join(format!("snowfall-theme-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r##"
+                "test_theme_a, test_theme_b" = "#102030"
+                test_theme_alias = "test_theme_z"
+                test_theme_z = "tomato"
+            "##,
+        )
+        .unwrap();
+        let result = load_theme(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(color_hex("test_theme_a"), Some("#102030".into()));
+        assert_eq!(color_hex("test_theme_b"), Some("#102030".into()));
+        assert_eq!(color_hex("test_theme_alias"), Some("#ff6347".into()));
+    }
+
+    #[test]
+    fn test_load_theme_reports_errors() {
+        assert!(matches!(
+            load_theme_str("test_theme_bad = \"#12345g\""),
+            Err(ThemeError::InvalidColor(err)) if err.name == "test_theme_bad"
+        ));
+        assert!(matches!(
+            load_theme_str("test_theme_num = 3"),
+            Err(ThemeError::NotAString(key)) if key == "test_theme_num"
+        ));
+        assert!(matches!(load_theme_str("= ="), Err(ThemeError::Parse(_))));
+        assert!(matches!(
+            load_theme("/nonexistent/theme.toml"),
+            Err(ThemeError::Io(_))
+        ));
+    }
+}
@@ -1,11 +1,15 @@
-mod console;
+pub mod console;
 mod macros;
 pub mod strings;
 
 pub mod prelude {
     pub use super::console::*;
+    pub use super::cprint;
     pub use super::cprintln;
     pub use super::debugln;
+    pub use super::error_ln;
+    pub use super::success_ln;
+    pub use super::warn_ln;
 
     pub mod core {
         pub use super::super::strings::*;
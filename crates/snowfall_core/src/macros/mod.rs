@@ -1,10 +1,12 @@
 #[macro_export]
 macro_rules! debugln {
     ($($arg:tt)*) => {{
-        // ANSI escape code: ESC[38;2;<r>;<g>;<b>m
-        const COLOR: &str = "\x1b[38;2;208;75;255m";
-        const RESET: &str = "\x1b[0m";
-        println!("{}{}{}", COLOR, format!($($arg)*), RESET);
+        if $crate::prelude::debug_output() {
+            // ANSI escape code: ESC[38;2;<r>;<g>;<b>m
+            const COLOR: &str = "\x1b[38;2;208;75;255m";
+            const RESET: &str = "\x1b[0m";
+            println!("{}{}{}", COLOR, format!($($arg)*), RESET);
+        }
     }};
 }
 
@@ -14,3 +16,31 @@ macro_rules! cprintln {
         $crate::prelude::cprintln_imp($color, format!($($arg)*).as_str());
     }};
 }
+
+#[macro_export]
+macro_rules! cprint {
+    ($color:expr, $($arg:tt)*) => {{
+        $crate::prelude::cprint_imp($color, format!($($arg)*).as_str());
+    }};
+}
+
+#[macro_export]
+macro_rules! error_ln {
+    ($($arg:tt)*) => {{
+        $crate::prelude::ecprintln_imp("error", format!($($arg)*).as_str());
+    }};
+}
+
+#[macro_export]
+macro_rules! warn_ln {
+    ($($arg:tt)*) => {{
+        $crate::prelude::ecprintln_imp("warn", format!($($arg)*).as_str());
+    }};
+}
+
+#[macro_export]
+macro_rules! success_ln {
+    ($($arg:tt)*) => {{
+        $crate::prelude::cprintln_imp("success", format!($($arg)*).as_str());
+    }};
+}
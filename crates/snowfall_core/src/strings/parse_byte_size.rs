@@ -0,0 +1,102 @@
+/// The error from `parse_byte_size`, with a message saying what was wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseByteSizeError {
+    message: String,
+}
+
+impl std::fmt::Display for ParseByteSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseByteSizeError {}
+
+/// Parses a human-readable size like `500MB`, `1.5GiB`, `2 TB`, or `1024`
+/// into bytes. Units are case-insensitive and follow the GNU tools: `KB`,
+/// `MB`, ... are powers of 1000, while `KiB`, `MiB`, ... and the single
+/// letters `K`, `M`, ... are powers of 1024.
+pub fn parse_byte_size(s: &str) -> Result<u64, ParseByteSizeError> {
+    let error = |message: String| ParseByteSizeError { message };
+
+    let value = s.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| error(format!("invalid size `{}`", s)))?;
+
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, base) = if let Some(prefix) = unit.strip_suffix("IB").filter(|p| !p.is_empty()) {
+        (prefix, 1024u64)
+    } else if let Some(prefix) = unit.strip_suffix('B').filter(|p| !p.is_empty()) {
+        (prefix, 1000)
+    } else {
+        (unit.as_str(), 1024)
+    };
+    let power = match prefix {
+        "" | "B" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        "E" => 6,
+        _ => {
+            return Err(error(format!(
+                "unknown size unit in `{}`, expected B, KB, MB, GB, TB, PB, or EB (or KiB, MiB, ...)",
+                s
+            )));
+        }
+    };
+
+    let bytes = number * base.pow(power) as f64;
+    if bytes >= u64::MAX as f64 {
+        return Err(error(format!("size `{}` is too large", s)));
+    }
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_bytes() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("0"), Ok(0));
+        assert_eq!(parse_byte_size("12B"), Ok(12));
+        assert_eq!(parse_byte_size(" 7 b "), Ok(7));
+    }
+
+    #[test]
+    fn test_decimal_units() {
+        assert_eq!(parse_byte_size("500MB"), Ok(500_000_000));
+        assert_eq!(parse_byte_size("2 TB"), Ok(2_000_000_000_000));
+        assert_eq!(parse_byte_size("1.5kb"), Ok(1500));
+        assert_eq!(parse_byte_size("1EB"), Ok(1_000_000_000_000_000_000));
+    }
+
+    #[test]
+    fn test_binary_units() {
+        assert_eq!(parse_byte_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_byte_size("2 kib"), Ok(2048));
+        assert_eq!(parse_byte_size("2K"), Ok(2048));
+        assert_eq!(parse_byte_size("1t"), Ok(1 << 40));
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        for s in [
+            "", "MB", "-5MB", "1.2.3KB", "10XB", "5 iB", "5 bytes", "20EiB",
+        ] {
+            assert!(parse_byte_size(s).is_err(), "{s}");
+        }
+        assert_eq!(
+            parse_byte_size("12 parsecs").unwrap_err().to_string(),
+            "unknown size unit in `12 parsecs`, expected B, KB, MB, GB, TB, PB, or EB (or KiB, MiB, ...)"
+        );
+    }
+}
@@ -4,11 +4,45 @@ pub fn to_comma_string<T>(n: T) -> String
 where
     T: std::fmt::Display,
 {
-    let s = n.to_string();
+    to_comma_string_with(n, ',', '.')
+}
+
+/// Formats a string as if it were a number like `to_comma_string`, but with
+/// the given digit group and decimal separators (e.g. 1234567.89 with `.`
+/// and `,` -> 1.234.567,89).
+pub fn to_comma_string_with<T>(n: T, group_sep: char, decimal_sep: char) -> String
+where
+    T: std::fmt::Display,
+{
+    group_digits(&n.to_string(), group_sep, decimal_sep, |digits| {
+        digits % 3 == 0
+    })
+}
+
+/// Formats a string as if it were a number and adds commas in the Indian
+/// style of lakhs and crores, grouping the last three digits and then every
+/// two (e.g. 10000000 -> 1,00,00,000).
+pub fn to_comma_string_indian<T>(n: T) -> String
+where
+    T: std::fmt::Display,
+{
+    group_digits(&n.to_string(), ',', '.', |digits| {
+        digits == 3 || (digits > 3 && digits % 2 == 1)
+    })
+}
+
+/// Adds `group_sep` to the integer part wherever `is_boundary` says the
+/// number of digits to the right of that spot ends a group
+fn group_digits(
+    s: &str,
+    group_sep: char,
+    decimal_sep: char,
+    is_boundary: impl Fn(usize) -> bool,
+) -> String {
     let (neg, s) = if let Some(rest) = s.strip_prefix('-') {
         (true, rest)
     } else {
-        (false, s.as_str())
+        (false, s)
     };
 
     let mut parts = s.splitn(2, '.');
@@ -19,14 +53,14 @@ where
     let chars: Vec<_> = int_part.chars().collect();
     let len = chars.len();
     for (i, c) in chars.iter().enumerate() {
-        if i > 0 && (len - i) % 3 == 0 {
-            res.push(',');
+        if i > 0 && is_boundary(len - i) {
+            res.push(group_sep);
         }
         res.push(*c);
     }
 
     if let Some(frac) = frac_part {
-        res.push('.');
+        res.push(decimal_sep);
         res.push_str(frac);
     }
 
@@ -81,4 +115,23 @@ mod tests {
         assert_eq!(to_comma_string(1005.2), "1,005.2");
         assert_eq!(to_comma_string(-1005.2), "-1,005.2");
     }
+
+    #[test]
+    fn test_custom_separators() {
+        assert_eq!(to_comma_string_with(1234567.89, '.', ','), "1.234.567,89");
+        assert_eq!(to_comma_string_with(-1234567, '.', ','), "-1.234.567");
+        assert_eq!(to_comma_string_with(1234567, ' ', ','), "1 234 567");
+        assert_eq!(to_comma_string_with("9876543.21", ' ', '.'), "9 876 543.21");
+        assert_eq!(to_comma_string_with(999, '.', ','), "999");
+    }
+
+    #[test]
+    fn test_indian_grouping() {
+        assert_eq!(to_comma_string_indian(999), "999");
+        assert_eq!(to_comma_string_indian(1000), "1,000");
+        assert_eq!(to_comma_string_indian(100000), "1,00,000");
+        assert_eq!(to_comma_string_indian(1234567), "12,34,567");
+        assert_eq!(to_comma_string_indian(10000000), "1,00,00,000");
+        assert_eq!(to_comma_string_indian(-1234567.5), "-12,34,567.5");
+    }
 }
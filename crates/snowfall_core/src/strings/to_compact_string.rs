@@ -0,0 +1,64 @@
+/// Formats a count in the largest SI unit that keeps it under 1000, with one
+/// decimal (e.g. 1234 -> 1.2K, 1500000 -> 1.5M). Counts under 1000 are left
+/// as they are.
+pub fn to_compact_string<T>(n: T) -> String
+where
+    T: num_traits::ToPrimitive,
+{
+    to_compact_string_prec(n, 1)
+}
+
+/// Formats a count like `to_compact_string`, but with the given number of
+/// decimals (e.g. 1234 with 2 decimals -> 1.23K).
+pub fn to_compact_string_prec<T>(n: T, decimals: usize) -> String
+where
+    T: num_traits::ToPrimitive,
+{
+    const SUFFIXES: [&str; 7] = ["", "K", "M", "G", "T", "P", "E"];
+
+    let value = n.to_f64().unwrap_or(0.0);
+    let sign = if value < 0.0 { "-" } else { "" };
+    let mut value = value.abs();
+    if value < 1000.0 {
+        return format!("{}{}", sign, value);
+    }
+
+    let mut suffix = 0;
+    // Compare the rounded value so 999,999 shows as 1.0M rather than 1000.0K
+    let scale = 10f64.powi(decimals as i32);
+    while suffix + 1 < SUFFIXES.len() && (value * scale).round() / scale >= 1000.0 {
+        value /= 1000.0;
+        suffix += 1;
+    }
+    format!("{}{:.*}{}", sign, decimals, value, SUFFIXES[suffix])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_counts() {
+        assert_eq!(to_compact_string(0), "0");
+        assert_eq!(to_compact_string(999), "999");
+        assert_eq!(to_compact_string(-42), "-42");
+    }
+
+    #[test]
+    fn test_suffixes() {
+        assert_eq!(to_compact_string(1000), "1.0K");
+        assert_eq!(to_compact_string(1234), "1.2K");
+        assert_eq!(to_compact_string(1_500_000), "1.5M");
+        assert_eq!(to_compact_string(7_260_000_000u64), "7.3G");
+        assert_eq!(to_compact_string(3_000_000_000_000u64), "3.0T");
+        assert_eq!(to_compact_string(-1_500_000i64), "-1.5M");
+    }
+
+    #[test]
+    fn test_precision_and_rounding() {
+        assert_eq!(to_compact_string_prec(1234, 0), "1K");
+        assert_eq!(to_compact_string_prec(1234, 2), "1.23K");
+        assert_eq!(to_compact_string(999_999), "1.0M");
+        assert_eq!(to_compact_string_prec(999_499, 0), "999K");
+    }
+}
@@ -0,0 +1,160 @@
+/// Whether byte sizes count in powers of 1024 or of 1000
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteBase {
+    /// Powers of 1024, labeled KiB, MiB, GiB, ...
+    Binary,
+    /// Powers of 1000, labeled KB, MB, GB, ... (as `ls` and disk vendors do)
+    Decimal,
+}
+
+/// Formats a number of bytes with two decimals in the largest unit that
+/// keeps it under 1024, from B up to EB (e.g. 1536 -> 1.50KB).
+///
+/// The units count in powers of 1024 despite their decimal names; use
+/// `to_pretty_byte_size_with` for correctly labeled sizes.
+pub fn to_pretty_byte_size<T>(n: T) -> String
+where
+    T: num_traits::PrimInt + num_traits::ToPrimitive,
+{
+    to_pretty_byte_size_prec(n, 2, false)
+}
+
+/// Formats a number of bytes like `to_pretty_byte_size`, but with the given
+/// number of decimals, and without them at all for a whole number of units
+/// if `trim_whole` is set (e.g. 1024 -> 1KB but 1536 -> 1.50KB).
+pub fn to_pretty_byte_size_prec<T>(n: T, decimals: usize, trim_whole: bool) -> String
+where
+    T: num_traits::PrimInt + num_traits::ToPrimitive,
+{
+    format_byte_size(
+        n,
+        1024.0,
+        &["B", "KB", "MB", "GB", "TB", "PB", "EB"],
+        decimals,
+        trim_whole,
+    )
+}
+
+/// Formats a number of bytes like `to_pretty_byte_size`, but in the given
+/// base and with its unit names (e.g. 1536 -> 1.50KiB or 1.54KB).
+pub fn to_pretty_byte_size_with<T>(n: T, base: ByteBase) -> String
+where
+    T: num_traits::PrimInt + num_traits::ToPrimitive,
+{
+    match base {
+        ByteBase::Binary => format_byte_size(
+            n,
+            1024.0,
+            &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"],
+            2,
+            false,
+        ),
+        ByteBase::Decimal => format_byte_size(
+            n,
+            1000.0,
+            &["B", "KB", "MB", "GB", "TB", "PB", "EB"],
+            2,
+            false,
+        ),
+    }
+}
+
+fn format_byte_size<T>(n: T, step: f64, units: &[&str], decimals: usize, trim_whole: bool) -> String
+where
+    T: num_traits::PrimInt + num_traits::ToPrimitive,
+{
+    let size = n.to_f64().unwrap();
+    let sign = if size < 0.0 { "-" } else { "" };
+    let mut size = size.abs();
+    let mut unit = 0;
+    // Compare the rounded size so values just under a unit boundary show as
+    // 1.00 of the next unit rather than, e.g., 1024.00 of this one
+    let scale = 10f64.powi(decimals as i32);
+    while unit + 1 < units.len() && (size * scale).round() / scale >= step {
+        size /= step;
+        unit += 1;
+    }
+
+    let mut number = format!("{:.*}", decimals, size);
+    if trim_whole
+        && let Some((whole, fraction)) = number.split_once('.')
+        && fraction.chars().all(|c| c == '0')
+    {
+        number = whole.to_string();
+    }
+    format!("{}{}{}", sign, number, units[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+    const TB: u64 = 1024 * GB;
+    const PB: u64 = 1024 * TB;
+
+    #[test]
+    fn test_small_sizes() {
+        assert_eq!(to_pretty_byte_size(0), "0.00B");
+        assert_eq!(to_pretty_byte_size(1023), "1023.00B");
+        assert_eq!(to_pretty_byte_size(1536), "1.50KB");
+        assert_eq!(to_pretty_byte_size(GB), "1.00GB");
+    }
+
+    #[test]
+    fn test_large_sizes() {
+        assert_eq!(to_pretty_byte_size(TB), "1.00TB");
+        assert_eq!(to_pretty_byte_size(PB + PB / 2), "1.50PB");
+        assert_eq!(to_pretty_byte_size(1024 * PB), "1.00EB");
+        assert_eq!(to_pretty_byte_size(u64::MAX), "16.00EB");
+    }
+
+    #[test]
+    fn test_unit_boundaries() {
+        assert_eq!(to_pretty_byte_size(1023 * GB + GB / 2), "1023.50GB");
+        assert_eq!(to_pretty_byte_size(TB - 1), "1.00TB");
+    }
+
+    #[test]
+    fn test_binary_and_decimal_bases() {
+        assert_eq!(to_pretty_byte_size_with(1536, ByteBase::Binary), "1.50KiB");
+        assert_eq!(to_pretty_byte_size_with(1536, ByteBase::Decimal), "1.54KB");
+        assert_eq!(to_pretty_byte_size_with(999, ByteBase::Decimal), "999.00B");
+        assert_eq!(to_pretty_byte_size_with(1000, ByteBase::Decimal), "1.00KB");
+        assert_eq!(to_pretty_byte_size_with(TB, ByteBase::Binary), "1.00TiB");
+        assert_eq!(
+            to_pretty_byte_size_with(2_500_000_000_000u64, ByteBase::Decimal),
+            "2.50TB"
+        );
+        assert_eq!(
+            to_pretty_byte_size_with(-1_500_000i64, ByteBase::Decimal),
+            "-1.50MB"
+        );
+    }
+
+    #[test]
+    fn test_precision() {
+        assert_eq!(to_pretty_byte_size_prec(1536, 0, false), "2KB");
+        assert_eq!(to_pretty_byte_size_prec(1536, 1, false), "1.5KB");
+        assert_eq!(to_pretty_byte_size_prec(1100, 1, false), "1.1KB");
+        assert_eq!(to_pretty_byte_size_prec(1023, 0, false), "1023B");
+        assert_eq!(to_pretty_byte_size_prec(GB - 1, 1, false), "1.0GB");
+    }
+
+    #[test]
+    fn test_trim_whole() {
+        assert_eq!(to_pretty_byte_size_prec(1024, 2, true), "1KB");
+        assert_eq!(to_pretty_byte_size_prec(0, 2, true), "0B");
+        assert_eq!(to_pretty_byte_size_prec(1536, 2, true), "1.50KB");
+        assert_eq!(to_pretty_byte_size_prec(TB - 1, 2, true), "1TB");
+        assert_eq!(to_pretty_byte_size_prec(-2048i64, 1, true), "-2KB");
+        assert_eq!(to_pretty_byte_size_prec(1024, 2, false), "1.00KB");
+    }
+
+    #[test]
+    fn test_negative_sizes() {
+        assert_eq!(to_pretty_byte_size(-2048i64), "-2.00KB");
+        assert_eq!(to_pretty_byte_size(-1i32), "-1.00B");
+        assert_eq!(to_pretty_byte_size(i64::MIN), "-8.00EB");
+    }
+}
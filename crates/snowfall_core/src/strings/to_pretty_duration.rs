@@ -0,0 +1,49 @@
+/// Formats a number of seconds for people to read: `23.4s` under a minute,
+/// `1m 23.4s` under an hour, and `2h 05m` from an hour up.
+pub fn to_pretty_duration(secs: f64) -> String {
+    // Round to the tenth of a second first so a value like 59.97 carries
+    // over into `1m 0.0s` rather than showing as `60.0s`
+    let tenths = (secs.max(0.0) * 10.0).round() as u64;
+    if tenths < 600 {
+        format!("{:.1}s", tenths as f64 / 10.0)
+    } else if tenths < 36_000 {
+        format!("{}m {:.1}s", tenths / 600, (tenths % 600) as f64 / 10.0)
+    } else {
+        let minutes = (tenths + 300) / 600;
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seconds() {
+        assert_eq!(to_pretty_duration(0.0), "0.0s");
+        assert_eq!(to_pretty_duration(0.26), "0.3s");
+        assert_eq!(to_pretty_duration(23.44), "23.4s");
+        assert_eq!(to_pretty_duration(-1.0), "0.0s");
+    }
+
+    #[test]
+    fn test_minutes() {
+        assert_eq!(to_pretty_duration(59.97), "1m 0.0s");
+        assert_eq!(to_pretty_duration(83.4), "1m 23.4s");
+        assert_eq!(to_pretty_duration(3599.9), "59m 59.9s");
+    }
+
+    #[test]
+    fn test_hours() {
+        assert_eq!(to_pretty_duration(3599.97), "1h 00m");
+        assert_eq!(
+            to_pretty_duration(2.0 * 3600.0 + 5.0 * 60.0 + 20.0),
+            "2h 05m"
+        );
+        assert_eq!(
+            to_pretty_duration(2.0 * 3600.0 + 59.0 * 60.0 + 45.0),
+            "3h 00m"
+        );
+        assert_eq!(to_pretty_duration(30.0 * 3600.0), "30h 00m");
+    }
+}
@@ -0,0 +1,411 @@
+use crate::snapshot::remote_snapshot_path;
+use anyhow::{Result, bail};
+use clap::{ArgAction, Parser, ValueEnum};
+use flate2::Compression;
+use snowfall_core::strings::parse_byte_size;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Default path of the git snapshot file kept in the monorepo
+pub const SNAPSHOT_FILE: &str = ".git-sync-snapshot.tar.gz";
+
+/// Options for a sync run. These are also the command-line arguments of the
+/// `sea-git-sync` binary, so each field's default matches the CLI default.
+#[derive(clap::Args, Clone, Debug)]
+pub struct SyncConfig {
+    /// Remote repository URL, or the name of a git remote such as `origin`
+    /// (repeatable, to mirror to several remotes)
+    #[arg(long = "remote", value_name = "URL")]
+    pub remotes: Vec<String>,
+    /// Branch to push to: one for all remotes, or one per --remote in order
+    #[arg(long = "branch", value_name = "NAME", default_value = "main")]
+    pub branches: Vec<String>,
+    /// Push to the monorepo's current branch under this prefix instead of
+    /// --branch, with slashes in it replaced by dashes (`release/1.0` is
+    /// pushed to `mirror/release-1.0` with `--branch-prefix mirror/`)
+    #[arg(long, value_name = "PREFIX", conflicts_with = "branches")]
+    pub branch_prefix: Option<String>,
+    /// Commit message for the sync commit. `{date}`, `{count}` (changed files),
+    /// and `{source_sha}` (the monorepo's HEAD) are filled in.
+    #[arg(long, default_value = "Sync changes")]
+    pub message: String,
+    /// Copy symlinks as files instead of links (`--copy-symlinks=false` to keep links)
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = true,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        action = ArgAction::Set
+    )]
+    pub copy_symlinks: bool,
+    /// Add a `.gitkeep` to empty directories in copied symlink targets, so the
+    /// mirror keeps them
+    #[arg(long)]
+    pub keep_empty_dirs: bool,
+    /// Stage the working tree from scratch, so the mirror drops every file that
+    /// is no longer synced, including ones in symlink targets that are now
+    /// excluded or gone
+    #[arg(long)]
+    pub prune: bool,
+    /// Perform all local steps but do not pull from or push to the remote
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Show a diffstat of the staged changes before committing
+    #[arg(long)]
+    pub show_diff: bool,
+    /// Glob pattern of files or directories to leave out of the sync (repeatable)
+    #[arg(long = "exclude", value_name = "GLOB")]
+    pub excludes: Vec<String>,
+    /// Glob pattern of files or directories to sync, leaving out everything
+    /// else (repeatable). Excludes still apply within included paths.
+    #[arg(long = "include", value_name = "GLOB")]
+    pub includes: Vec<String>,
+    /// Path of the git snapshot file kept in the monorepo
+    #[arg(long, value_name = "PATH", default_value = SNAPSHOT_FILE)]
+    pub snapshot_path: PathBuf,
+    /// Create the initial snapshot from a shallow clone with this many commits
+    #[arg(long, value_name = "N")]
+    pub depth: Option<u32>,
+    /// Git executable to run instead of the `git` found on PATH
+    #[arg(long, value_name = "PATH", default_value = "git")]
+    pub git_binary: String,
+    /// Output format: colorized status lines or a single JSON summary on stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub output: OutputFormat,
+    /// Number of times to retry a failed pull or push
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+    /// Seconds to wait before the first retry, doubling after each attempt
+    #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+    pub retry_delay: u64,
+    /// SSH private key for git clone, fetch, pull, and push, used instead of the
+    /// keys from the SSH agent or config
+    #[arg(long, value_name = "PATH")]
+    pub ssh_key: Option<PathBuf>,
+    /// Kill git clone, fetch, pull, and push commands that run longer than this
+    /// many seconds, so a dead connection can't hang the sync
+    #[arg(long, value_name = "SECONDS")]
+    pub network_timeout: Option<u64>,
+    /// Author of the sync commit, as "Name <email>"
+    #[arg(long, value_name = "IDENTITY")]
+    pub author: Option<String>,
+    /// Committer of the sync commit, as "Name <email>"
+    #[arg(long, value_name = "IDENTITY")]
+    pub committer: Option<String>,
+    /// Sign the sync commit, using git's configured `gpg.format` (openpgp, ssh, or x509)
+    #[arg(long)]
+    pub sign: bool,
+    /// Key to sign the sync commit with (implies --sign)
+    #[arg(long, value_name = "KEY")]
+    pub signing_key: Option<String>,
+    /// Create the branch on the remote if it doesn't exist yet, instead of failing
+    #[arg(long)]
+    pub branch_create: bool,
+    /// Skip the pull and force-push, overwriting any divergent remote history
+    #[arg(long)]
+    pub force_push: bool,
+    /// Only print errors and the final status
+    #[arg(long, short)]
+    pub quiet: bool,
+    /// Also print how long each command takes and the git executable it runs
+    /// (repeat, as -vv, to also print the environment variables set for it)
+    #[arg(long, short, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Append a timestamped record of each sync to this file
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    /// Subdirectory to sync, instead of the current directory. The snapshot
+    /// path, `.git`, and symlink copies are all relative to this directory.
+    #[arg(long, visible_alias = "workdir", value_name = "PATH")]
+    pub subdir: Option<PathBuf>,
+    /// Create an annotated tag on the synced commit and push it after a successful sync
+    #[arg(long, value_name = "NAME")]
+    pub tag: Option<String>,
+    /// Message for the annotated tag (defaults to the tag name)
+    #[arg(long, value_name = "MESSAGE", requires = "tag")]
+    pub tag_message: Option<String>,
+    /// Replace the tag if it already exists, locally or on the remote
+    #[arg(long, requires = "tag")]
+    pub force_tag: bool,
+    /// How to integrate remote changes when pulling
+    #[arg(long, value_enum, default_value_t = MergeStrategy::Merge)]
+    pub merge_strategy: MergeStrategy,
+    /// Amend the previous sync commit instead of adding a new one, so the
+    /// mirror keeps a single rolling sync commit
+    #[arg(long)]
+    pub squash: bool,
+    /// Commit even when nothing changed, so every run leaves a commit on the
+    /// remote (e.g. as a heartbeat for scheduled syncs)
+    #[arg(long)]
+    pub commit_empty: bool,
+    /// Keep the `.git` directory between runs and reuse it instead of
+    /// extracting the snapshot each time. The snapshot is only refreshed when
+    /// missing, when older than --snapshot-max-age, or with --refresh-snapshot.
+    #[arg(long)]
+    pub keep_git: bool,
+    /// With --keep-git, refresh the snapshot after this run
+    #[arg(long, requires = "keep_git")]
+    pub refresh_snapshot: bool,
+    /// With --keep-git, refresh the snapshot once it is older than this many hours
+    #[arg(long, value_name = "HOURS", requires = "keep_git")]
+    pub snapshot_max_age: Option<u64>,
+    /// How to garbage collect the repository before archiving it into the snapshot
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = GcMode::Aggressive)]
+    pub gc: GcMode,
+    /// Skip garbage collection before archiving the snapshot (same as --gc=off)
+    #[arg(long, conflicts_with = "gc")]
+    pub no_gc: bool,
+    /// Shell command to run in the synced folder before each sync commit, e.g.
+    /// a formatter. Its changes are included in the commit, and the sync is
+    /// aborted if it fails.
+    #[arg(long, value_name = "COMMAND")]
+    pub pre_commit_hook: Option<String>,
+    /// Shell command to run in the synced folder once every remote is synced
+    /// and the symlinks are restored. The sync fails if it does.
+    #[arg(long, value_name = "COMMAND")]
+    pub post_sync_hook: Option<String>,
+    /// Gzip level of the snapshot, from 0 (stored uncompressed, fastest) to 9
+    /// (smallest, slowest)
+    #[arg(long, value_name = "LEVEL", default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub snapshot_compression: u32,
+    /// Fail instead of replacing the snapshot when the new one would be larger
+    /// than this, e.g. `500MB` or a plain number of bytes. `KB`, `MB`, ... are
+    /// powers of 1000, while `KiB`, `MiB`, ... and `K`, `M`, ... are powers of 1024.
+    #[arg(long, value_name = "SIZE", value_parser = |s: &str| parse_byte_size(s).map_err(|e| e.to_string()))]
+    pub max_snapshot_size: Option<u64>,
+    /// What to do with copied files that the monorepo tracks with Git LFS
+    #[arg(long, value_enum, value_name = "MODE", default_value = "copy")]
+    pub lfs: LfsMode,
+    /// Warn about copied files larger than this, which may belong in Git LFS
+    /// (`0` to turn the warning off)
+    #[arg(long, value_name = "SIZE", default_value = "10MB", value_parser = |s: &str| parse_byte_size(s).map_err(|e| e.to_string()))]
+    pub large_file_warning: u64,
+}
+
+impl SyncConfig {
+    /// Configuration for syncing to `remote`, with every other option at its
+    /// command-line default
+    pub fn new(remote: impl Into<String>) -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            config: SyncConfig,
+        }
+
+        let mut config = Defaults::parse_from(["sea-git-sync", "--remote", ""]).config;
+        config.remotes = vec![remote.into()];
+        config
+    }
+
+    /// The remotes to sync to, each paired with its branch and the snapshot
+    /// that holds its history
+    pub(crate) fn targets(&self) -> Result<Vec<SyncTarget<'_>>> {
+        if self.keep_git && self.remotes.len() > 1 {
+            bail!("--keep-git only supports a single --remote");
+        }
+        sync_targets(&self.remotes, &self.branches, &self.snapshot_path)
+    }
+
+    /// The compression level for writing snapshots
+    pub(crate) fn compression(&self) -> Compression {
+        Compression::new(self.snapshot_compression)
+    }
+
+    /// The garbage collection mode, taking --no-gc into account
+    pub(crate) fn gc_mode(&self) -> GcMode {
+        if self.no_gc { GcMode::Off } else { self.gc }
+    }
+}
+
+/// Pair each remote with its branch (one for all remotes, or one per remote)
+/// and its snapshot. The first remote uses `snapshot_path` itself; each
+/// additional remote gets a snapshot named after it alongside.
+fn sync_targets<'a>(
+    remotes: &'a [String],
+    branches: &'a [String],
+    snapshot_path: &Path,
+) -> Result<Vec<SyncTarget<'a>>> {
+    if remotes.is_empty() {
+        bail!("No remote given, pass --remote or set `remote` in the config file");
+    }
+    if branches.len() != 1 && branches.len() != remotes.len() {
+        bail!(
+            "Got {} --branch values for {} remotes; pass one --branch for all remotes, or one per --remote",
+            branches.len(),
+            remotes.len()
+        );
+    }
+
+    let targets = remotes
+        .iter()
+        .enumerate()
+        .map(|(i, remote)| SyncTarget {
+            remote,
+            branch: &branches[i.min(branches.len() - 1)],
+            snapshot_path: if i == 0 {
+                snapshot_path.to_path_buf()
+            } else {
+                remote_snapshot_path(snapshot_path, remote)
+            },
+        })
+        .collect::<Vec<_>>();
+
+    // Different URLs for the same repository would share a snapshot
+    let mut remotes = HashSet::new();
+    let mut snapshot_paths = HashSet::new();
+    for target in &targets {
+        if !remotes.insert(target.remote) || !snapshot_paths.insert(&target.snapshot_path) {
+            bail!("Remote {} is listed more than once", target.remote);
+        }
+    }
+    Ok(targets)
+}
+
+/// The branch that `source_branch` is mirrored to under `prefix`. Slashes in
+/// the source branch are replaced by dashes, so that each source branch maps
+/// to a single level below the prefix.
+pub(crate) fn prefixed_branch(prefix: &str, source_branch: &str) -> String {
+    format!("{}{}", prefix, source_branch.replace('/', "-"))
+}
+
+/// A remote and branch to sync to
+#[derive(Debug)]
+pub(crate) struct SyncTarget<'a> {
+    pub(crate) remote: &'a str,
+    pub(crate) branch: &'a str,
+    /// Snapshot of this remote's git history
+    pub(crate) snapshot_path: PathBuf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// How `git pull` integrates remote changes into the sync commit
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Always create a merge commit (`--no-ff`)
+    Merge,
+    /// Rebase the sync commit onto the remote branch (`--rebase`)
+    Rebase,
+    /// Only fast-forward, failing if the remote has diverged (`--ff-only`)
+    FfOnly,
+}
+
+impl MergeStrategy {
+    pub(crate) fn pull_flag(self) -> &'static str {
+        match self {
+            MergeStrategy::Merge => "--no-ff",
+            MergeStrategy::Rebase => "--rebase",
+            MergeStrategy::FfOnly => "--ff-only",
+        }
+    }
+}
+
+/// How the repository is garbage collected before each snapshot. A thorough
+/// repack is slow on large repositories but keeps the snapshot small.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcMode {
+    /// Let git decide whether any housekeeping is needed (`gc --auto`)
+    Auto,
+    /// Fully repack and prune unreachable objects (`gc --aggressive --prune=now`)
+    Aggressive,
+    /// Archive the repository as it is
+    Off,
+}
+
+impl GcMode {
+    pub(crate) fn gc_args(self) -> Option<&'static [&'static str]> {
+        match self {
+            GcMode::Auto => Some(&["gc", "--auto"]),
+            GcMode::Aggressive => Some(&["gc", "--aggressive", "--prune=now"]),
+            GcMode::Off => None,
+        }
+    }
+}
+
+/// How copied files that the monorepo tracks with Git LFS (a `filter=lfs`
+/// attribute) are synced
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfsMode {
+    /// Commit their full content
+    Copy,
+    /// Leave them out of the mirror
+    Skip,
+    /// Commit an LFS pointer to their content instead
+    Pointer,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::check_snapshot_size;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_max_snapshot_size() {
+        #[derive(Parser)]
+        struct Args {
+            #[command(flatten)]
+            config: SyncConfig,
+        }
+        let parse = |size: &str| {
+            Args::try_parse_from(["sea-git-sync", "--max-snapshot-size", size])
+                .map(|args| args.config.max_snapshot_size)
+        };
+
+        assert_eq!(parse("500MB").unwrap(), Some(500_000_000));
+        // Reported in the units it was given in
+        let error = check_snapshot_size(600_000_000, parse("500MB").unwrap()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Snapshot is 600.00MB, over the --max-snapshot-size of 500.00MB; keeping the previous snapshot"
+        );
+        assert!(check_snapshot_size(500_000_000, parse("500MB").unwrap()).is_ok());
+        assert_eq!(parse("500MiB").unwrap(), Some(500 << 20));
+        assert_eq!(parse("2 TB").unwrap(), Some(2_000_000_000_000));
+        assert!(parse("99999999999GB").is_err());
+        assert!(parse("10XB").is_err());
+    }
+
+    #[test]
+    fn test_prefixed_branch() {
+        assert_eq!(prefixed_branch("mirror/", "main"), "mirror/main");
+        assert_eq!(
+            prefixed_branch("mirror/", "release/1.0"),
+            "mirror/release-1.0"
+        );
+        assert_eq!(prefixed_branch("", "feature/a/b"), "feature-a-b");
+    }
+
+    #[test]
+    fn test_sync_targets_pair_remotes_with_branches() {
+        let snapshot = Path::new(SNAPSHOT_FILE);
+        let remotes = strings(&[
+            "git@github.com:org/repo.git",
+            "https://gitlab.internal/repo",
+        ]);
+
+        let branches = strings(&["main"]);
+        let targets = sync_targets(&remotes, &branches, snapshot).unwrap();
+        assert_eq!(targets[1].branch, "main");
+        assert_eq!(targets[0].snapshot_path, snapshot);
+        assert_ne!(targets[1].snapshot_path, snapshot);
+
+        let branches = strings(&["main", "mirror"]);
+        let targets = sync_targets(&remotes, &branches, snapshot).unwrap();
+        assert_eq!(targets[1].branch, "mirror");
+
+        let branches = strings(&["main", "mirror", "extra"]);
+        assert!(sync_targets(&remotes, &branches, snapshot).is_err());
+
+        let remotes = strings(&["git@github.com:org/repo.git", "git@github.com:org/repo.git"]);
+        assert!(sync_targets(&remotes, &strings(&["main"]), snapshot).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use crate::output::{Verbosity, child_stdout, verbosity};
+use anyhow::{Context, Result};
+use regex::Regex;
+use snowfall_core::strings::to_pretty_duration;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// Git executable used by all git invocations, set at the start of each sync
+static GIT_BINARY: RwLock<String> = RwLock::new(String::new());
+
+/// Set the git executable used by subsequent git invocations
+pub(crate) fn set_git_binary(git_binary: &str) {
+    *GIT_BINARY.write().unwrap_or_else(PoisonError::into_inner) = git_binary.to_string();
+}
+
+/// Longest a git command that talks to a remote may run, set at the start of each sync
+static NETWORK_TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// SSH key used by all git commands that talk to a remote, set at the start of each sync
+static SSH_KEY: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Git subcommands that reach a remote and can hang on a dead connection
+const NETWORK_COMMANDS: &[&str] = &["clone", "fetch", "ls-remote", "pull", "push"];
+
+/// Set the timeout for subsequent git commands that talk to a remote
+pub(crate) fn set_network_timeout(timeout: Option<Duration>) {
+    *NETWORK_TIMEOUT
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = timeout;
+}
+
+/// Set the SSH key for subsequent git commands that talk to a remote
+pub(crate) fn set_ssh_key(ssh_key: Option<&Path>) {
+    *SSH_KEY.write().unwrap_or_else(PoisonError::into_inner) = ssh_key.map(Path::to_path_buf);
+}
+
+/// The `GIT_SSH_COMMAND` for a git command with these arguments, when it talks
+/// to a remote and an SSH key is set
+fn ssh_command_env(args: &[&str]) -> Option<(&'static str, String)> {
+    if !args
+        .first()
+        .is_some_and(|subcommand| NETWORK_COMMANDS.contains(subcommand))
+    {
+        return None;
+    }
+    let ssh_key = SSH_KEY.read().unwrap_or_else(PoisonError::into_inner);
+    ssh_key
+        .as_deref()
+        .map(|key| ("GIT_SSH_COMMAND", ssh_command(key)))
+}
+
+/// An ssh command line that only offers `key`, quoted for the shell git runs it with
+fn ssh_command(key: &Path) -> String {
+    let key = key.to_string_lossy().replace('\'', "'\\''");
+    format!("ssh -i '{}' -o IdentitiesOnly=yes", key)
+}
+
+/// The timeout applying to a git command with these arguments, if any
+fn network_timeout(args: &[&str]) -> Option<Duration> {
+    args.first()
+        .filter(|subcommand| NETWORK_COMMANDS.contains(subcommand))
+        .and(
+            *NETWORK_TIMEOUT
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+}
+
+/// The configured git executable, defaulting to `git` on PATH
+pub(crate) fn git_binary() -> String {
+    let git_binary = GIT_BINARY.read().unwrap_or_else(PoisonError::into_inner);
+    if git_binary.is_empty() {
+        "git".to_string()
+    } else {
+        git_binary.clone()
+    }
+}
+
+/// How a git command that didn't fail finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GitOutcome {
+    Success,
+    /// `git commit` had no staged changes to commit
+    NothingToCommit {
+        exit_code: i32,
+    },
+}
+
+/// Run a git command with standard error handling. Arguments are strings or
+/// paths, which are passed on as they are even if they aren't valid UTF-8.
+pub(crate) fn git<A: AsRef<OsStr>>(args: &[A]) -> Result<GitOutcome> {
+    git_with_env(args, &[])
+}
+
+/// Run a git command with additional environment variables set
+pub(crate) fn git_with_env<A: AsRef<OsStr>>(
+    args: &[A],
+    envs: &[(&str, &str)],
+) -> Result<GitOutcome> {
+    let run = run_git(args, envs, child_stdout())?;
+    if !run.status.success() {
+        // For git commit, exit code 1 with no staged changes is acceptable
+        if args[0].as_ref() == "commit" && run.status.code() == Some(1) {
+            return Ok(GitOutcome::NothingToCommit { exit_code: 1 });
+        }
+        return Err(run.error());
+    }
+    Ok(GitOutcome::Success)
+}
+
+/// Run a git command, returning the last lines it wrote to stderr
+pub(crate) fn git_stderr(args: &[&str]) -> Result<Vec<String>> {
+    let run = run_git(args, &[], child_stdout())?;
+    if !run.status.success() {
+        return Err(run.error());
+    }
+    Ok(run.stderr_tail)
+}
+
+/// Run a git command and return its captured stdout
+pub(crate) fn git_output<A: AsRef<OsStr>>(args: &[A]) -> Result<String> {
+    let run = run_git(args, &[], Stdio::piped())?;
+    if !run.status.success() {
+        return Err(run.error());
+    }
+    Ok(String::from_utf8_lossy(&run.stdout).into_owned())
+}
+
+/// Whether the current directory has a usable `.git` of its own. A missing or
+/// broken one would make git fall back to the enclosing monorepo's repository.
+pub(crate) fn has_own_git_dir() -> bool {
+    Path::new(".git").is_dir()
+        && git_output(&["rev-parse", "--git-dir"]).is_ok_and(|dir| dir.trim() == ".git")
+}
+
+/// Number of trailing stderr lines attached to the error of a failed git command
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Result of a finished git process
+struct GitRun {
+    status: std::process::ExitStatus,
+    /// Captured stdout, if it was piped
+    stdout: Vec<u8>,
+    /// The last few lines git wrote to stderr
+    stderr_tail: Vec<String>,
+}
+
+impl GitRun {
+    /// Error describing the failure, with the tail of stderr as its cause
+    fn error(&self) -> anyhow::Error {
+        let message = format!(
+            "Git command failed with exit code: {}",
+            self.status.code().unwrap_or(-1)
+        );
+        if self.stderr_tail.is_empty() {
+            anyhow::anyhow!(message)
+        } else {
+            anyhow::anyhow!(self.stderr_tail.join("\n")).context(message)
+        }
+    }
+}
+
+/// Spawn git, streaming its stderr to the terminal while keeping the tail of
+/// it so failures can be reported without scrolling back through the output
+fn run_git<A: AsRef<OsStr>>(args: &[A], envs: &[(&str, &str)], stdout: Stdio) -> Result<GitRun> {
+    // Only the subcommand decides how git is run, and arguments that aren't
+    // valid UTF-8 are only shown lossily
+    let shown = args
+        .iter()
+        .map(|arg| arg.as_ref().to_string_lossy())
+        .collect::<Vec<_>>();
+    let shown = shown.iter().map(|arg| arg.as_ref()).collect::<Vec<&str>>();
+    let ssh_env = ssh_command_env(&shown);
+    let mut all_envs = envs.to_vec();
+    all_envs.extend(
+        ssh_env
+            .as_ref()
+            .map(|(name, value)| (*name, value.as_str())),
+    );
+    echo_command("git", &git_binary(), &shown, &all_envs);
+    let started = Instant::now();
+    let mut child = Command::new(git_binary())
+        .args(args)
+        .envs(all_envs.iter().copied())
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute git command")?;
+
+    // When quiet, stderr is only kept for error reporting
+    let echo = verbosity() >= Verbosity::Normal;
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|stderr| std::thread::spawn(move || tee_stderr_tail(stderr, echo)));
+
+    let stdout_reader = child.stdout.take().map(|mut child_stdout| {
+        std::thread::spawn(move || {
+            let mut captured = Vec::new();
+            child_stdout.read_to_end(&mut captured).map(|_| captured)
+        })
+    });
+
+    let status = wait_with_timeout(&mut child, network_timeout(&shown), &shown)?;
+    echo_elapsed(started);
+    let captured = match stdout_reader.map(|handle| handle.join()) {
+        Some(Ok(read)) => read.context("Failed to read git output")?,
+        _ => Vec::new(),
+    };
+    let stderr_tail = stderr_reader
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default();
+
+    Ok(GitRun {
+        status,
+        stdout: captured,
+        stderr_tail,
+    })
+}
+
+/// Echo a command about to run. Verbose output names the actual executable
+/// rather than `name`, and trace output adds the environment variables set.
+fn echo_command(name: &str, program: &str, args: &[&str], envs: &[(&str, &str)]) {
+    let program = if verbosity() >= Verbosity::Verbose {
+        program
+    } else {
+        name
+    };
+    status!("555", "> [{} {}](goldenrod)", program, args.join(" "));
+    if verbosity() >= Verbosity::Trace {
+        for (name, value) in envs {
+            status!("555", "  [{}={}](goldenrod)", name, value);
+        }
+    }
+}
+
+/// Echo how long a command took, when verbose
+fn echo_elapsed(started: Instant) {
+    if verbosity() >= Verbosity::Verbose {
+        status!(
+            "555",
+            "  [took {}](#666)",
+            to_pretty_duration(started.elapsed().as_secs_f64())
+        );
+    }
+}
+
+/// Wait for a git child process, killing it if it runs past `timeout`
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    args: &[&str],
+) -> Result<ExitStatus> {
+    let Some(timeout) = timeout else {
+        return child.wait().context("Failed to wait for git command");
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for git command")? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::anyhow!(
+                "git {} timed out after {}s",
+                args.first().unwrap_or(&""),
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Copy a child's stderr to our stderr line by line (if `echo` is set),
+/// returning the last lines
+fn tee_stderr_tail(stderr: impl Read, echo: bool) -> Vec<String> {
+    let mut reader = BufReader::new(stderr);
+    let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut line = Vec::new();
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        if echo {
+            let _ = std::io::stderr().write_all(&line);
+        }
+        if tail.len() == STDERR_TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+        line.clear();
+    }
+    tail.into_iter().filter(|l| !l.is_empty()).collect()
+}
+
+/// Run command in specific directory
+pub(crate) fn run_command_in_dir(cmd: &str, args: &[&str], dir: &Path) -> Result<()> {
+    let ssh_env = ssh_command_env(args);
+    let envs = ssh_env
+        .as_ref()
+        .map(|(name, value)| (*name, value.as_str()))
+        .into_iter()
+        .collect::<Vec<_>>();
+    echo_command(cmd, cmd, args, &envs);
+    let started = Instant::now();
+    let mut child = Command::new(cmd)
+        .args(args)
+        .envs(envs.iter().copied())
+        .current_dir(dir)
+        .stdout(child_stdout())
+        .spawn()
+        .with_context(|| format!("Failed to execute {} command", cmd))?;
+    let status = wait_with_timeout(&mut child, network_timeout(args), args)?;
+    echo_elapsed(started);
+
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Command failed with exit code: {}",
+            status.code().unwrap_or(-1)
+        ));
+    }
+    Ok(())
+}
+
+/// Write the exclude patterns to `.git/info/exclude` so `git add` skips them
+pub(crate) fn write_git_excludes(git_dir: &Path, patterns: &[String]) -> Result<()> {
+    let info_dir = git_dir.join("info");
+    fs::create_dir_all(&info_dir)
+        .with_context(|| format!("Failed to create directory: {}", info_dir.display()))?;
+
+    let mut contents = patterns.join("\n");
+    contents.push('\n');
+    fs::write(info_dir.join("exclude"), contents).context("Failed to write git exclude file")
+}
+
+/// Counts of the changes staged in the index
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StagedChanges {
+    pub added: usize,
+    pub modified: usize,
+    pub deleted: usize,
+}
+
+impl StagedChanges {
+    /// Count the staged changes in `git status --porcelain` output. Renames and
+    /// type changes count as modifications, copies as additions.
+    pub(crate) fn parse(porcelain: &str) -> Self {
+        let mut changes = Self::default();
+        for line in porcelain.lines() {
+            match line.chars().next() {
+                Some('A' | 'C') => changes.added += 1,
+                Some('M' | 'R' | 'T') => changes.modified += 1,
+                Some('D') => changes.deleted += 1,
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    pub fn total(&self) -> usize {
+        self.added + self.modified + self.deleted
+    }
+}
+
+/// What a `git push --progress` sent, from its "Writing objects" line
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TransferStats {
+    pub(crate) objects: u64,
+    pub(crate) bytes: u64,
+}
+
+impl TransferStats {
+    /// Read the stats from the stderr of a push. Progress updates on one line
+    /// are separated by carriage returns, so the last match is the final one.
+    /// A push with nothing to send has no stats.
+    pub(crate) fn parse(stderr: &[String]) -> Option<Self> {
+        let re = Regex::new(r"Writing objects: 100% \((\d+)/\d+\), ([\d.]+) (bytes|KiB|MiB|GiB)")
+            .unwrap();
+        let captures = stderr
+            .iter()
+            .rev()
+            .find_map(|line| re.captures_iter(line).last())?;
+        let size: f64 = captures[2].parse().ok()?;
+        let scale = match &captures[3] {
+            "KiB" => 1u64 << 10,
+            "MiB" => 1 << 20,
+            "GiB" => 1 << 30,
+            _ => 1,
+        };
+        Some(Self {
+            objects: captures[1].parse().ok()?,
+            bytes: (size * scale as f64) as u64,
+        })
+    }
+}
+
+/// The number of objects in the repository and the disk space they take, as
+/// reported by `git count-objects -v`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ObjectCounts {
+    pub(crate) objects: u64,
+    pub(crate) bytes: u64,
+}
+
+impl ObjectCounts {
+    /// Count the objects of the current repository
+    pub(crate) fn read() -> Result<Self> {
+        Ok(Self::parse(&git_output(&["count-objects", "-v"])?))
+    }
+
+    /// Read the counts from `git count-objects -v`, which gives loose and
+    /// packed objects separately and sizes in KiB. Garbage (files in the
+    /// object directory that aren't objects) counts towards the size.
+    pub(crate) fn parse(output: &str) -> Self {
+        let mut counts = Self {
+            objects: 0,
+            bytes: 0,
+        };
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<u64>() else {
+                continue;
+            };
+            match key {
+                "count" | "in-pack" => counts.objects += value,
+                "size" | "size-pack" | "size-garbage" => counts.bytes += value << 10,
+                _ => {}
+            }
+        }
+        counts
+    }
+}
+
+/// Split a git identity of the form "Name <email>" into its name and email
+pub(crate) fn parse_identity(identity: &str) -> Result<(&str, &str)> {
+    let re = Regex::new(r"^\s*(.+?)\s*<([^<>\s]+)>\s*$").unwrap();
+    let captures = re.captures(identity).with_context(|| {
+        format!(
+            "Invalid identity \"{}\", expected \"Name <email>\"",
+            identity
+        )
+    })?;
+    let name = captures.get(1).unwrap().as_str();
+    let email = captures.get(2).unwrap().as_str();
+    Ok((name, email))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(
+            parse_identity("Sync Bot <bot@example.com>").unwrap(),
+            ("Sync Bot", "bot@example.com")
+        );
+        assert!(parse_identity("Sync Bot").is_err());
+        assert!(parse_identity("<bot@example.com>").is_err());
+    }
+
+    #[test]
+    fn test_staged_changes_parse() {
+        let porcelain = "A  new.txt\nM  changed.txt\nMM both.txt\nR  old.txt -> new_name.txt\nD  gone.txt\n M unstaged.txt\n?? untracked.txt\n";
+        assert_eq!(
+            StagedChanges::parse(porcelain),
+            StagedChanges {
+                added: 1,
+                modified: 3,
+                deleted: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_object_counts_parse() {
+        let output = "count: 12\nsize: 48\nin-pack: 1190\npacks: 1\nsize-pack: 2048\nprune-packable: 0\ngarbage: 0\nsize-garbage: 0\n";
+        assert_eq!(
+            ObjectCounts::parse(output),
+            ObjectCounts {
+                objects: 1202,
+                bytes: 2096 << 10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_transfer_stats_parse() {
+        let stderr = [
+            "Enumerating objects: 1204, done.".to_string(),
+            "Writing objects:  40% (482/1204)\rWriting objects: 100% (1204/1204), 2.50 MiB | 1.20 MiB/s, done.".to_string(),
+            "Total 1204 (delta 310), reused 0 (delta 0), pack-reused 0".to_string(),
+        ];
+        assert_eq!(
+            TransferStats::parse(&stderr),
+            Some(TransferStats {
+                objects: 1204,
+                bytes: 5 << 19,
+            })
+        );
+        assert_eq!(
+            TransferStats::parse(&[
+                "Writing objects: 100% (3/3), 289 bytes | 289.00 KiB/s, done.".to_string()
+            ]),
+            Some(TransferStats {
+                objects: 3,
+                bytes: 289,
+            })
+        );
+        assert_eq!(
+            TransferStats::parse(&["Everything up-to-date".to_string()]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ssh_command_quotes_key() {
+        assert_eq!(
+            ssh_command(Path::new("/keys/deploy key")),
+            "ssh -i '/keys/deploy key' -o IdentitiesOnly=yes"
+        );
+        assert_eq!(
+            ssh_command(Path::new("/keys/it's")),
+            r"ssh -i '/keys/it'\''s' -o IdentitiesOnly=yes"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_with_timeout_kills_slow_command() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let started = Instant::now();
+        let result = wait_with_timeout(&mut child, Some(Duration::from_millis(200)), &["push"]);
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let mut child = Command::new("true").spawn().unwrap();
+        let status = wait_with_timeout(&mut child, Some(Duration::from_secs(5)), &["push"]);
+        assert!(status.unwrap().success());
+    }
+
+    #[test]
+    fn test_tee_stderr_tail_keeps_last_lines() {
+        let input: String = (1..=15).map(|i| format!("line {}\n", i)).collect();
+        let tail = tee_stderr_tail(input.as_bytes(), false);
+        assert_eq!(tail.len(), STDERR_TAIL_LINES);
+        assert_eq!(tail.first().unwrap(), "line 6");
+        assert_eq!(tail.last().unwrap(), "line 15");
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// Ignore file in the sync root, in `.gitignore` syntax, listing paths that are
+/// never synced
+pub(crate) const IGNORE_FILE: &str = ".git-sync-ignore";
+
+/// A shell-style glob pattern (`*`, `?`, and `**`) used to include or exclude paths.
+///
+/// As with `.gitignore`, a pattern without a `/` matches an entry's name at any
+/// depth, while a pattern containing a `/` matches the path relative to the
+/// sync root. A leading `/` also anchors the pattern to the root.
+#[derive(Debug)]
+pub(crate) struct GlobPattern {
+    regex: Regex,
+    match_name: bool,
+}
+
+impl GlobPattern {
+    pub(crate) fn new(pattern: &str) -> Result<Self> {
+        let trimmed = pattern.trim_start_matches("./").trim_end_matches('/');
+        let match_name = !trimmed.contains('/');
+        let trimmed = trimmed.trim_start_matches('/');
+        let mut source = String::from("^");
+        let mut chars = trimmed.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        source.push_str("(?:.*/)?");
+                    } else {
+                        source.push_str(".*");
+                    }
+                }
+                '*' => source.push_str("[^/]*"),
+                '?' => source.push_str("[^/]"),
+                c => source.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        source.push('$');
+
+        let regex =
+            Regex::new(&source).with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        Ok(Self { regex, match_name })
+    }
+
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        let subject = if self.match_name {
+            path.file_name().map(|name| name.to_string_lossy())
+        } else {
+            Some(path.to_string_lossy())
+        };
+        subject.is_some_and(|s| self.regex.is_match(&s))
+    }
+
+    /// Whether the path or any of its parent directories matches
+    pub(crate) fn matches_within(&self, path: &Path) -> bool {
+        path.ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && *p != Path::new("."))
+            .any(|p| self.matches(p))
+    }
+}
+
+/// The patterns listed in an ignore file, skipping blank lines and comments.
+/// A missing file has no patterns.
+pub(crate) fn read_ignore_file(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ignore file: {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Whether a path is synced under the include patterns: everything is when
+/// there are none, otherwise only paths matching one or inside a match
+pub(crate) fn is_included(includes: &[GlobPattern], path: &Path) -> bool {
+    includes.is_empty() || includes.iter().any(|glob| glob.matches_within(path))
+}
+
+/// `.gitignore` lines that ignore everything except the include patterns (and
+/// the contents of included directories). Excludes written after these lines
+/// take precedence. Empty when there are no includes.
+pub(crate) fn gitignore_includes(includes: &[String]) -> Vec<String> {
+    if includes.is_empty() {
+        return Vec::new();
+    }
+
+    // Ignore every file, but keep directories so the re-included files below
+    // can still be reached
+    let mut lines = vec!["*".to_string(), "!*/".to_string()];
+    for pattern in includes {
+        let trimmed = pattern.trim_start_matches("./").trim_end_matches('/');
+        lines.push(format!("!{}", trimmed));
+        if trimmed.contains('/') {
+            lines.push(format!("!{}/**", trimmed));
+        } else {
+            lines.push(format!("!**/{}/**", trimmed));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches_name_at_any_depth() {
+        let glob = GlobPattern::new("node_modules").unwrap();
+        assert!(glob.matches(Path::new("./node_modules")));
+        assert!(glob.matches(Path::new("./web/app/node_modules")));
+        assert!(!glob.matches(Path::new("./node_modules_extra")));
+    }
+
+    #[test]
+    fn test_glob_wildcards() {
+        let glob = GlobPattern::new("*.log").unwrap();
+        assert!(glob.matches(Path::new("./build.log")));
+        assert!(glob.matches(Path::new("./logs/nested/debug.log")));
+        assert!(!glob.matches(Path::new("./build.log.txt")));
+
+        let glob = GlobPattern::new("file?.txt").unwrap();
+        assert!(glob.matches(Path::new("./file1.txt")));
+        assert!(!glob.matches(Path::new("./file10.txt")));
+    }
+
+    #[test]
+    fn test_glob_with_slash_is_relative_to_root() {
+        let glob = GlobPattern::new("docs/internal/").unwrap();
+        assert!(glob.matches(Path::new("./docs/internal")));
+        assert!(!glob.matches(Path::new("./src/docs/internal")));
+
+        let glob = GlobPattern::new("**/target").unwrap();
+        assert!(glob.matches(Path::new("./target")));
+        assert!(glob.matches(Path::new("./crates/foo/target")));
+
+        let glob = GlobPattern::new("/target").unwrap();
+        assert!(glob.matches(Path::new("./target")));
+        assert!(!glob.matches(Path::new("./crates/foo/target")));
+    }
+
+    #[test]
+    fn test_read_ignore_file() {
+        let path = std::env::temp_dir().join(format!("sea-git-sync-ignore-{}", std::process::id()));
+        fs::write(&path, "# build output\ntarget/  \n\n*.log\n!keep.log\n").unwrap();
+        assert_eq!(
+            read_ignore_file(&path).unwrap(),
+            ["target/", "*.log", "!keep.log"]
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(read_ignore_file(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_included() {
+        let includes = [
+            GlobPattern::new("src/lib/").unwrap(),
+            GlobPattern::new("LICENSE").unwrap(),
+        ];
+        assert!(is_included(&includes, Path::new("./src/lib")));
+        assert!(is_included(&includes, Path::new("./src/lib/nested/mod.rs")));
+        assert!(is_included(&includes, Path::new("./vendor/LICENSE")));
+        assert!(!is_included(&includes, Path::new("./src/main.rs")));
+        assert!(!is_included(&includes, Path::new("./vendor/src/lib")));
+        assert!(is_included(&[], Path::new("./docs/index.md")));
+    }
+
+    #[test]
+    fn test_gitignore_includes() {
+        assert!(gitignore_includes(&[]).is_empty());
+        assert_eq!(
+            gitignore_includes(&["src/lib/".to_string(), "LICENSE".to_string()]),
+            [
+                "*",
+                "!*/",
+                "!src/lib",
+                "!src/lib/**",
+                "!LICENSE",
+                "!**/LICENSE/**"
+            ]
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::symlinks::{SharedReplacements, undo_symlink_replacements};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+/// What the sync in progress has changed in the working tree, to be undone
+/// if it is interrupted
+#[derive(Default)]
+struct Cleanup {
+    /// Symlinks replaced by copies so far
+    symlinks: Option<SharedReplacements>,
+    /// A `.git` directory extracted by this run, which is to be removed
+    git_dir: Option<PathBuf>,
+}
+
+static CLEANUP: Mutex<Cleanup> = Mutex::new(Cleanup {
+    symlinks: None,
+    git_dir: None,
+});
+
+/// Set the symlinks to restore if interrupted
+pub(crate) fn set_symlinks(symlinks: Option<SharedReplacements>) {
+    CLEANUP
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .symlinks = symlinks;
+}
+
+/// Set the `.git` directory to remove if interrupted (an absolute path, as
+/// the working directory may have changed by then)
+pub(crate) fn set_git_dir(git_dir: Option<PathBuf>) {
+    CLEANUP
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .git_dir = git_dir;
+}
+
+/// Undo what the sync in progress has changed in the working tree, restoring
+/// the replaced symlinks and removing the `.git` it extracted, then exit with
+/// the status of a process killed by SIGINT. Meant to be called from a Ctrl-C
+/// handler; a snapshot left half written is recovered by the next run.
+///
+/// The locks are held until the process exits, so the sync can't replace any
+/// more symlinks in the meantime.
+pub fn exit_interrupted() -> ! {
+    let cleanup = CLEANUP.lock().unwrap_or_else(PoisonError::into_inner);
+    status!("warn", "Interrupted, restoring the working tree...");
+    let _symlinks = cleanup.symlinks.as_ref().map(|symlinks| {
+        let mut replacements = symlinks.lock().unwrap_or_else(PoisonError::into_inner);
+        undo_symlink_replacements(std::mem::take(&mut *replacements));
+        replacements
+    });
+    if let Some(git_dir) = &cleanup.git_dir {
+        let _ = fs::remove_dir_all(git_dir);
+    }
+    std::process::exit(130)
+}
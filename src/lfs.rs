@@ -0,0 +1,188 @@
+use crate::config::LfsMode;
+use crate::git::git_output;
+use crate::symlinks::CopiedFiles;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use snowfall_core::strings::{ByteBase, to_pretty_byte_size_with};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// First line of a Git LFS pointer file
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Paths are passed to `git check-attr` in batches of this many, to stay
+/// well clear of command line length limits
+const CHECK_ATTR_BATCH: usize = 500;
+
+/// Apply `mode` to the copied files that the monorepo tracks with Git LFS,
+/// and warn about any other copied file larger than `warn_size` bytes.
+///
+/// Whether a file is tracked with LFS is decided by the attributes of its
+/// original, as the copy sits where the monorepo's `.gitattributes` patterns
+/// may not match it. Originals outside of a git repository never are.
+pub(crate) fn check_copied_files(
+    copied: &[CopiedFiles],
+    mode: LfsMode,
+    warn_size: u64,
+) -> Result<()> {
+    let mut skipped = 0;
+    let mut pointers = 0;
+    for group in copied {
+        let files = group
+            .files
+            .iter()
+            .filter_map(|(original, copy)| {
+                let size = fs::metadata(copy).ok()?.len();
+                let is_large = warn_size > 0 && size > warn_size;
+                (mode != LfsMode::Copy || is_large).then_some((original, copy, size))
+            })
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            continue;
+        }
+        let originals = files
+            .iter()
+            .map(|(original, _, _)| *original)
+            .collect::<Vec<_>>();
+        let tracked = lfs_tracked(&group.source_dir, &originals);
+
+        for (original, copy, size) in files {
+            let is_lfs = tracked.contains(original);
+            match mode {
+                LfsMode::Skip if is_lfs => {
+                    fs::remove_file(copy)
+                        .with_context(|| format!("Failed to skip LFS file: {}", copy.display()))?;
+                    skipped += 1;
+                }
+                LfsMode::Pointer if is_lfs => pointers += usize::from(write_pointer(copy)?),
+                _ if warn_size > 0 && size > warn_size => {
+                    let hint = if is_lfs {
+                        ", which is tracked by Git LFS; pass --lfs pointer or --lfs skip to leave its content out"
+                    } else {
+                        ", consider tracking it with Git LFS"
+                    };
+                    // In powers of 1000, as --large-file-warning is given
+                    status!(
+                        "warn",
+                        "Copying large file [{}](filename) ({}, over the --large-file-warning of {}){}",
+                        copy.display(),
+                        to_pretty_byte_size_with(size, ByteBase::Decimal),
+                        to_pretty_byte_size_with(warn_size, ByteBase::Decimal),
+                        hint
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+    if skipped > 0 {
+        status!("#39C", "Skipped [{}](number) Git LFS files", skipped);
+    }
+    if pointers > 0 {
+        status!(
+            "#39C",
+            "Replaced [{}](number) Git LFS files with pointers",
+            pointers
+        );
+    }
+    Ok(())
+}
+
+/// Which of the `files` in `dir` have the `filter=lfs` attribute
+fn lfs_tracked<'a>(dir: &Path, files: &[&'a PathBuf]) -> HashSet<&'a PathBuf> {
+    let mut tracked = HashSet::new();
+    for batch in files.chunks(CHECK_ATTR_BATCH) {
+        let mut args = vec![
+            OsString::from("-C"),
+            dir.as_os_str().to_owned(),
+            OsString::from("check-attr"),
+            OsString::from("-z"),
+            OsString::from("filter"),
+            OsString::from("--"),
+        ];
+        args.extend(batch.iter().map(|file| file.as_os_str().to_owned()));
+        // Not being in a repository just means there are no LFS files
+        let Ok(output) = git_output(&args) else {
+            return tracked;
+        };
+        let lfs_paths = parse_check_attr(&output);
+        tracked.extend(
+            batch
+                .iter()
+                .filter(|file| lfs_paths.contains(file.to_string_lossy().as_ref())),
+        );
+    }
+    tracked
+}
+
+/// The paths that `git check-attr -z filter` reports as having `filter=lfs`
+fn parse_check_attr(output: &str) -> HashSet<&str> {
+    let fields = output.split('\0').collect::<Vec<_>>();
+    fields
+        .chunks_exact(3)
+        .filter(|entry| entry[2] == "lfs")
+        .map(|entry| entry[0])
+        .collect()
+}
+
+/// Replace the file with a Git LFS pointer to its content, returning whether
+/// it was replaced. A file that is already a pointer, as when the monorepo's
+/// LFS content isn't checked out, is left as it is.
+fn write_pointer(path: &Path) -> Result<bool> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open: {}", path.display()))?;
+    let mut start = [0; POINTER_VERSION.len()];
+    if file.read_exact(&mut start).is_ok() && start == POINTER_VERSION.as_bytes() {
+        return Ok(false);
+    }
+
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read: {}", path.display()))?;
+    fs::write(path, pointer(&format!("{:x}", hasher.finalize()), size))
+        .with_context(|| format!("Failed to write LFS pointer: {}", path.display()))?;
+    Ok(true)
+}
+
+/// The content of a Git LFS pointer file
+fn pointer(oid: &str, size: u64) -> String {
+    format!("{}\noid sha256:{}\nsize {}\n", POINTER_VERSION, oid, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_check_attr() {
+        let output = "big.bin\0filter\0lfs\0notes.txt\0filter\0unspecified\0a b.psd\0filter\0lfs\0";
+        let tracked = parse_check_attr(output);
+        assert_eq!(tracked, HashSet::from(["big.bin", "a b.psd"]));
+    }
+
+    #[test]
+    fn test_write_pointer() {
+        let dir = std::env::temp_dir().join(format!("sea-git-sync-lfs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("big.bin");
+        fs::write(&path, "hello\n").unwrap();
+
+        assert!(write_pointer(&path).unwrap());
+        let expected = pointer(
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03",
+            6,
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        // Already a pointer
+        assert!(!write_pointer(&path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,683 @@
+//! Sync a subdirectory of a monorepo to an external git repository, keeping the
+//! mirror's git history in a compressed snapshot next to the synced files.
+
+#[macro_use]
+mod output;
+
+mod config;
+mod git;
+mod glob;
+mod interrupt;
+mod lfs;
+mod log;
+mod message;
+mod remote;
+mod snapshot;
+mod status;
+mod symlinks;
+
+pub use config::{GcMode, LfsMode, MergeStrategy, OutputFormat, SNAPSHOT_FILE, SyncConfig};
+pub use git::StagedChanges;
+pub use interrupt::exit_interrupted;
+pub use status::{RemoteStatus, SyncStatus, status};
+
+use anyhow::{Context, Result, bail};
+use config::{SyncTarget, prefixed_branch};
+use git::{
+    GitOutcome, ObjectCounts, git, git_output, git_with_env, has_own_git_dir, parse_identity,
+    run_command_in_dir, write_git_excludes,
+};
+use glob::{GlobPattern, IGNORE_FILE, gitignore_includes, read_ignore_file};
+use lfs::check_copied_files;
+use log::{format_utc_timestamp, unix_now};
+use message::{MessageValues, expand_message, source_sha, uses_source_sha};
+use output::Verbosity;
+use remote::{
+    branch_needs_creating, claim_git_dir, ensure_tag_available, git_dir_owner, merge_in_progress,
+    pull_and_push, push_tag, remote_is_at_head, resolve_remote, squashable_head, validate_remote,
+};
+use snapshot::{
+    create_initial_snapshot, create_snapshot, extract_snapshot, recover_interrupted_snapshot,
+    snapshot_files, snapshot_is_due,
+};
+use snowfall_core::strings::{to_comma_string, to_pretty_byte_size};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use symlinks::{ReplacedSymlinks, add_gitkeep_files};
+
+/// Summary of a sync run
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Largest number of files staged for any remote's sync commit
+    pub files_added: usize,
+    /// Whether a sync commit was created for any remote
+    pub commit_created: bool,
+    /// Whether the push succeeded for every remote
+    pub push_succeeded: bool,
+    /// The commit the first remote's branch is at after the sync
+    pub commit: Option<String>,
+    /// Total size of the updated snapshots, if the run got as far as pushing
+    pub snapshot_size_bytes: Option<u64>,
+    pub duration: Duration,
+    /// The outcome for each remote, in the order they were synced
+    pub remotes: Vec<RemoteReport>,
+}
+
+/// Summary of syncing to one remote
+#[derive(Debug, Default)]
+pub struct RemoteReport {
+    pub remote: String,
+    pub branch: String,
+    /// Number of files staged for the sync commit
+    pub files_added: usize,
+    pub commit_created: bool,
+    pub push_succeeded: bool,
+    /// The commit the remote branch is at after a successful push
+    pub commit: Option<String>,
+    /// Size of the updated snapshot, if the run got as far as pushing
+    pub snapshot_size_bytes: Option<u64>,
+    /// The sync commit's message, with its placeholders filled in
+    pub message: String,
+}
+
+impl SyncReport {
+    /// Record a remote's outcome, folding it into the overall totals
+    fn add_remote(&mut self, remote: RemoteReport) {
+        self.files_added = self.files_added.max(remote.files_added);
+        self.commit_created |= remote.commit_created;
+        self.push_succeeded =
+            remote.push_succeeded && (self.push_succeeded || self.remotes.is_empty());
+        if self.remotes.is_empty() {
+            self.commit = remote.commit.clone();
+        }
+        if let Some(size) = remote.snapshot_size_bytes {
+            self.snapshot_size_bytes = Some(self.snapshot_size_bytes.unwrap_or(0) + size);
+        }
+        self.remotes.push(remote);
+    }
+
+    /// The report as a single-line JSON object, as printed by `--output json`
+    pub fn to_json(&self) -> String {
+        let remotes = self
+            .remotes
+            .iter()
+            .map(|remote| {
+                format!(
+                    "{{\"remote\":{},\"branch\":{},\"files_added\":{},\"commit_created\":{},\"push_succeeded\":{},\"commit\":{},\"snapshot_size_bytes\":{}}}",
+                    json_string(&remote.remote),
+                    json_string(&remote.branch),
+                    remote.files_added,
+                    remote.commit_created,
+                    remote.push_succeeded,
+                    json_optional_string(remote.commit.as_deref()),
+                    json_number(remote.snapshot_size_bytes)
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"files_added\":{},\"commit_created\":{},\"push_succeeded\":{},\"commit\":{},\"snapshot_size_bytes\":{},\"duration_secs\":{:.3},\"remotes\":[{}]}}",
+            self.files_added,
+            self.commit_created,
+            self.push_succeeded,
+            json_optional_string(self.commit.as_deref()),
+            json_number(self.snapshot_size_bytes),
+            self.duration.as_secs_f32(),
+            remotes.join(",")
+        )
+    }
+}
+
+/// Sync the current directory (or `config.subdir`) to the configured remote.
+///
+/// Status output goes to stdout as configured by `config.output`,
+/// `config.quiet`, and `config.verbose`. The working directory is restored
+/// before returning.
+pub fn sync(config: &SyncConfig) -> Result<SyncReport> {
+    let start = Instant::now();
+    let mut report = in_sync_dir(config, sync_to_remote)?;
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+/// Configure status output and git as `config` asks, then call `run` with
+/// the config as it applies from within the `--subdir` (if any), which is the
+/// working directory until `run` returns
+fn in_sync_dir<T>(config: &SyncConfig, run: impl FnOnce(&SyncConfig) -> Result<T>) -> Result<T> {
+    let verbosity = match (config.quiet, config.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Trace,
+    };
+    output::configure(config.output, verbosity);
+
+    let mut config = config.clone();
+    // The initial clone runs in its own directory, so the key path must not be relative
+    if let Some(ssh_key) = &config.ssh_key {
+        config.ssh_key = Some(std::path::absolute(ssh_key)?);
+    }
+    let original_dir = enter_subdir(&mut config)?;
+    git::set_git_binary(&config.git_binary);
+    git::set_network_timeout(config.network_timeout.map(Duration::from_secs));
+    git::set_ssh_key(config.ssh_key.as_deref());
+
+    let result = resolve_remotes(&mut config)
+        .and_then(|()| resolve_branch_prefix(&mut config))
+        .and_then(|()| run(&config));
+    // Whatever the run changed has been put back by now
+    interrupt::set_symlinks(None);
+    interrupt::set_git_dir(None);
+    if let Some(dir) = original_dir {
+        std::env::set_current_dir(&dir)
+            .with_context(|| format!("Failed to return to directory: {}", dir.display()))?;
+    }
+    result
+}
+
+/// Make the `--subdir` (if any) the working directory, so that staging,
+/// symlink copying, and the snapshot are all scoped to it. Options that
+/// refer to paths outside the subdirectory are made absolute first.
+///
+/// Returns the previous working directory, to be restored after the sync.
+fn enter_subdir(config: &mut SyncConfig) -> Result<Option<PathBuf>> {
+    let Some(subdir) = &config.subdir else {
+        return Ok(None);
+    };
+
+    if let Some(log_file) = &config.log_file {
+        config.log_file = Some(std::path::absolute(log_file)?);
+    }
+    if config.git_binary.contains(std::path::MAIN_SEPARATOR) {
+        config.git_binary = std::path::absolute(&config.git_binary)?
+            .to_string_lossy()
+            .into_owned();
+    }
+    // A remote that is a local path (rather than a URL) is relative to where we started
+    for remote in &mut config.remotes {
+        if Path::new(remote.as_str()).exists() {
+            *remote = std::path::absolute(&remote)?.to_string_lossy().into_owned();
+        }
+    }
+
+    let original_dir = std::env::current_dir().context("Failed to read current directory")?;
+    std::env::set_current_dir(subdir)
+        .with_context(|| format!("Failed to enter subdirectory: {}", subdir.display()))?;
+    Ok(Some(original_dir))
+}
+
+/// Replace any remote given as a git remote name with its URL, so snapshots,
+/// logs, and the `.git` ownership check all see the URL
+fn resolve_remotes(config: &mut SyncConfig) -> Result<()> {
+    for remote in &mut config.remotes {
+        *remote = resolve_remote(remote)?;
+    }
+    Ok(())
+}
+
+/// With `--branch-prefix`, push to the monorepo's current branch under the
+/// prefix. The branch is read from the directory above, as the synced one may
+/// have a `.git` of its own (the mirror's).
+fn resolve_branch_prefix(config: &mut SyncConfig) -> Result<()> {
+    let Some(prefix) = &config.branch_prefix else {
+        return Ok(());
+    };
+    let source_branch = git_output(&["-C", "..", "rev-parse", "--abbrev-ref", "HEAD"])
+        .context("Failed to detect the monorepo's current branch for --branch-prefix")?;
+    let source_branch = source_branch.trim();
+    if source_branch == "HEAD" {
+        bail!("--branch-prefix needs a branch checked out in the monorepo, but HEAD is detached");
+    }
+    config.branches = vec![prefixed_branch(prefix, source_branch)];
+    Ok(())
+}
+
+/// Sync the working tree to each remote in turn. Symlinks are copied once up
+/// front; every remote then gets its own commit on top of its own history.
+///
+/// If syncing to a remote fails, the sync stops there: remotes before it are
+/// fully synced and later ones are left for the next run. Symlinks are always
+/// restored. The `.git` is removed (its snapshot is untouched until a push
+/// succeeds), except after a pull that needs its merge resolved by hand; the
+/// next run then syncs that remote first.
+fn sync_to_remote(config: &SyncConfig) -> Result<SyncReport> {
+    let mut targets = config.targets()?;
+    for target in &targets {
+        validate_remote(target.remote)?;
+    }
+    let ignored = read_ignore_file(Path::new(IGNORE_FILE))?;
+    let (includes, excludes) = symlink_patterns(config, &ignored)?;
+
+    if let Some(owner) = git_dir_owner() {
+        targets.sort_by_key(|target| target.remote != owner);
+    }
+
+    let git_excludes = git_exclude_patterns(config, &targets, &ignored);
+
+    let replaced_symlinks = ReplacedSymlinks::new(Vec::new());
+    interrupt::set_symlinks(Some(replaced_symlinks.shared()));
+    if config.copy_symlinks {
+        status!("#39C", "Copying symlinks as files...");
+        replaced_symlinks.copy_symlinks(Path::new("."), &includes, &excludes);
+        check_copied_files(
+            &replaced_symlinks.copied_files(),
+            config.lfs,
+            config.large_file_warning,
+        )?;
+    }
+    if config.keep_empty_dirs {
+        for path in replaced_symlinks.paths() {
+            add_gitkeep_files(&path).with_context(|| {
+                format!("Failed to keep empty directories in {}", path.display())
+            })?;
+        }
+    }
+
+    let mut report = SyncReport::default();
+    for target in &targets {
+        if targets.len() > 1 {
+            status!(
+                "#39C",
+                "Syncing to [{}](filename) [{}](key)...",
+                target.remote,
+                target.branch
+            );
+        }
+        let remote_report = sync_target(config, target, &git_excludes, &replaced_symlinks)
+            .with_context(|| format!("Failed to sync to {}", target.remote))?;
+        if let Some(log_file) = &config.log_file {
+            log::append_sync_log(log_file, target, &remote_report)?;
+        }
+        report.add_remote(remote_report);
+    }
+
+    drop(replaced_symlinks);
+    if let Some(hook) = &config.post_sync_hook {
+        run_hook("post-sync", hook)?;
+    }
+    Ok(report)
+}
+
+/// The include and exclude patterns for the symlink walk, given the patterns
+/// read from the ignore file. Negated ignore file patterns can only be honored
+/// by git itself, so the walk skips everything else the ignore file lists.
+fn symlink_patterns(
+    config: &SyncConfig,
+    ignored: &[String],
+) -> Result<(Vec<GlobPattern>, Vec<GlobPattern>)> {
+    let excludes = ignored
+        .iter()
+        .filter(|pattern| !pattern.starts_with('!'))
+        .chain(&config.excludes)
+        .map(|pattern| GlobPattern::new(pattern))
+        .collect::<Result<Vec<_>>>()?;
+    let includes = config
+        .includes
+        .iter()
+        .map(|pattern| GlobPattern::new(pattern))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((includes, excludes))
+}
+
+/// The patterns for `.git/info/exclude`, given the patterns read from the
+/// ignore file. Excludes come after the includes so they win on conflict, and
+/// those given as options after the ignore file's. The snapshots, their
+/// checksums, and their backups never belong in the mirror.
+fn git_exclude_patterns(
+    config: &SyncConfig,
+    targets: &[SyncTarget],
+    ignored: &[String],
+) -> Vec<String> {
+    let mut git_excludes = gitignore_includes(&config.includes);
+    git_excludes.extend(ignored.iter().cloned());
+    git_excludes.extend(config.excludes.iter().cloned());
+    for target in targets {
+        for path in snapshot_files(&target.snapshot_path) {
+            if path.is_relative() {
+                git_excludes.push(format!("/{}", path.display()));
+            }
+        }
+    }
+    git_excludes
+}
+
+/// Commit the working tree on top of one remote's history, then pull, push,
+/// and refresh its snapshot
+fn sync_target(
+    config: &SyncConfig,
+    target: &SyncTarget,
+    git_excludes: &[String],
+    replaced_symlinks: &ReplacedSymlinks,
+) -> Result<RemoteReport> {
+    let mut report = RemoteReport {
+        remote: target.remote.to_string(),
+        branch: target.branch.to_string(),
+        ..Default::default()
+    };
+
+    let snapshot_path = target.snapshot_path.as_path();
+    let git_dir = Path::new(".git");
+    recover_interrupted_snapshot(snapshot_path)?;
+    let reuse_git = config.keep_git && has_own_git_dir();
+    if !reuse_git && !snapshot_path.exists() {
+        status!("#39C", "No snapshot found, creating initial clone...");
+        create_initial_snapshot(
+            target.remote,
+            snapshot_path,
+            config.depth,
+            config.compression(),
+        )?;
+    }
+
+    status!("#39C", "Syncing changes to remote repository...");
+
+    // A .git that was already here (kept by --keep-git, or left to resolve a
+    // merge by hand) may hold work the snapshot lacks, so only one extracted
+    // by this run is removed if the sync fails
+    let mut git_dir_guard = GitDirGuard::new(git_dir, git_dir.exists() || config.keep_git)?;
+    if reuse_git {
+        status!("#39C", "Reusing existing .git directory");
+    } else if !git_dir.exists() {
+        ensure_clean_dir(git_dir)?;
+        extract_snapshot(snapshot_path, git_dir)?;
+    }
+    claim_git_dir(target.remote)?;
+    write_git_excludes(git_dir, git_excludes)?;
+
+    if let Some(hook) = &config.pre_commit_hook {
+        run_hook("pre-commit", hook)?;
+    }
+
+    // Staging on top of an empty index leaves out every file that isn't
+    // synced any more, such as one in a symlink target that is now excluded
+    if config.prune {
+        git(&["rm", "-r", "--cached", "--quiet", "--ignore-unmatch", "."])?;
+    }
+    for path in replaced_symlinks.paths() {
+        git(&[OsStr::new("add"), OsStr::new("--force"), path.as_os_str()])?;
+    }
+    git(&["add", "."])?;
+    let staged = StagedChanges::parse(&git_output(&["status", "--porcelain"])?);
+    status!(
+        "#39C",
+        "Staged changes: [{}](number) added, [{}](number) modified, [{}](number) deleted",
+        staged.added,
+        staged.modified,
+        staged.deleted
+    );
+    if config.show_diff {
+        git(&["diff", "--cached", "--stat"])?;
+    }
+    report.files_added = staged.total();
+
+    // When squashing, amend HEAD if it is the previous sync commit. With no
+    // staged changes there is nothing to fold in, so the run proceeds as usual.
+    let squashed_over = if config.squash && report.files_added > 0 {
+        squashable_head(config)?
+    } else {
+        None
+    };
+
+    let source_sha = if uses_source_sha(&config.message) {
+        source_sha()
+    } else {
+        String::new()
+    };
+    let message = expand_message(
+        &config.message,
+        &MessageValues {
+            date: &format_utc_timestamp(unix_now())[..10],
+            count: staged.total(),
+            source_sha: &source_sha,
+        },
+    );
+    report.message = message.clone();
+    let mut commit_args = vec!["commit", "-m", &message];
+    if squashed_over.is_some() {
+        status!("#39C", "Squashing into the previous sync commit...");
+        commit_args.push("--amend");
+    }
+    if config.commit_empty {
+        commit_args.push("--allow-empty");
+    }
+    if let Some(author) = &config.author {
+        parse_identity(author)?;
+        commit_args.extend(["--author", author]);
+    }
+    let gpg_sign = config
+        .signing_key
+        .as_ref()
+        .map(|key| format!("--gpg-sign={}", key));
+    if let Some(gpg_sign) = &gpg_sign {
+        commit_args.push(gpg_sign);
+    } else if config.sign {
+        commit_args.push("--gpg-sign");
+    }
+    let committer_env = match &config.committer {
+        Some(committer) => {
+            let (name, email) = parse_identity(committer)?;
+            vec![("GIT_COMMITTER_NAME", name), ("GIT_COMMITTER_EMAIL", email)]
+        }
+        None => vec![],
+    };
+    let committed = git_with_env(&commit_args, &committer_env);
+    let outcome = if config.sign || config.signing_key.is_some() {
+        committed.context(
+            "Failed to create signed commit, check the signing key and gpg.format setting",
+        )?
+    } else {
+        committed?
+    };
+    if let GitOutcome::NothingToCommit { .. } = outcome {
+        status!("#39C", "No changes to commit");
+    }
+    report.commit_created = outcome == GitOutcome::Success;
+
+    // Until the post-push steps finish, the previous snapshot can be put back
+    let mut snapshot_backup = None;
+    if config.dry_run {
+        // Show what would have been pushed
+        git(&["log", "--stat", "-1"])?;
+        status!("warn", "DRY RUN — no changes pushed");
+    } else {
+        if let Some(tag) = &config.tag {
+            ensure_tag_available(config, target, tag)?;
+        }
+        let create_branch = branch_needs_creating(config, target)?;
+
+        // Without a new commit there is only something to sync if either side
+        // has moved on since the last run
+        let up_to_date = !report.commit_created && remote_is_at_head(target);
+        if up_to_date {
+            status!("#39C", "Already up to date, nothing to sync");
+        } else {
+            // A pull that stopped for its merge or rebase to be resolved by
+            // hand keeps .git for the next run. Any other failure removes it,
+            // unpushed commit and all, and the next run starts again from the
+            // snapshot.
+            let pushed = pull_and_push(config, target, squashed_over.as_deref(), create_branch);
+            if pushed.is_err() && merge_in_progress(git_dir) {
+                git_dir_guard.keep();
+            }
+            if !pushed? {
+                return Err(anyhow::anyhow!("Push failed, not updating snapshot"));
+            }
+        }
+        report.push_succeeded = true;
+        report.commit = Some(git_output(&["rev-parse", "HEAD"])?.trim().to_string());
+        if let Some(tag) = &config.tag {
+            push_tag(config, target, tag)?;
+        }
+        if (!config.keep_git && !up_to_date)
+            || config.refresh_snapshot
+            || snapshot_is_due(snapshot_path, config.snapshot_max_age)
+        {
+            status!(
+                "#39C",
+                "Updating snapshot (compression level [{}](number))...",
+                config.snapshot_compression
+            );
+            if let Some(gc_args) = config.gc_mode().gc_args() {
+                let before = ObjectCounts::read();
+                git(gc_args)?;
+                // The summary is only informational, so a failed count is not an error
+                if let (Ok(before), Ok(after)) = (before, ObjectCounts::read()) {
+                    status!(
+                        "#39C",
+                        "Garbage collection removed [{}](number) objects and [{}](number), leaving [{}](number) objects in [{}](number)",
+                        to_comma_string(before.objects.saturating_sub(after.objects)),
+                        to_pretty_byte_size(before.bytes.saturating_sub(after.bytes)),
+                        to_comma_string(after.objects),
+                        to_pretty_byte_size(after.bytes)
+                    );
+                }
+            }
+            snapshot_backup = Some(create_snapshot(
+                git_dir,
+                snapshot_path,
+                config.max_snapshot_size,
+                config.compression(),
+            )?);
+        } else {
+            status!("#39C", "Keeping existing snapshot");
+        }
+
+        // Display the snapshot file size (since it can be abnormally large)
+        let snapshot_size = fs::metadata(snapshot_path)
+            .with_context(|| format!("Failed to read snapshot: {}", snapshot_path.display()))?
+            .len();
+        report.snapshot_size_bytes = Some(snapshot_size);
+        status!(
+            "#39C",
+            "Snapshot size: [{}](number) [{}](filename)",
+            to_pretty_byte_size(snapshot_size),
+            snapshot_path.display()
+        );
+    }
+
+    if !config.keep_git {
+        git_dir_guard.keep();
+        fs::remove_dir_all(git_dir).context("Failed to clean up .git directory")?;
+    }
+    if let Some(snapshot_backup) = snapshot_backup {
+        snapshot_backup.keep();
+    }
+    Ok(report)
+}
+
+/// Removes the `.git` directory when dropped, unless it is to be kept, so a
+/// failed sync doesn't leave a half-used repository for the next run
+struct GitDirGuard<'a> {
+    path: &'a Path,
+    keep: bool,
+}
+
+impl<'a> GitDirGuard<'a> {
+    /// Guard `path`, which is also removed if the sync is interrupted unless
+    /// it is to be kept
+    fn new(path: &'a Path, keep: bool) -> Result<Self> {
+        if !keep {
+            interrupt::set_git_dir(Some(std::path::absolute(path)?));
+        }
+        Ok(Self { path, keep })
+    }
+
+    /// Leave the `.git` directory in place
+    fn keep(&mut self) {
+        self.keep = true;
+        interrupt::set_git_dir(None);
+    }
+}
+
+impl Drop for GitDirGuard<'_> {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(self.path);
+            interrupt::set_git_dir(None);
+        }
+    }
+}
+
+// Helper functions
+
+/// Ensure directory exists and is empty
+fn ensure_clean_dir(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)
+            .with_context(|| format!("Failed to remove existing directory: {}", dir.display()))?;
+    }
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    Ok(())
+}
+
+/// Run a hook command through the shell in the working directory
+fn run_hook(name: &str, command: &str) -> Result<()> {
+    status!("#39C", "Running {} hook...", name);
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    run_command_in_dir(shell, &[flag, command], Path::new("."))
+        .with_context(|| format!("The {} hook failed", name))
+}
+
+/// Quote a string as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An optional string as JSON, `null` when absent
+fn json_optional_string(value: Option<&str>) -> String {
+    value.map_or("null".to_string(), json_string)
+}
+
+/// An optional number as JSON, `null` when absent
+fn json_number(value: Option<u64>) -> String {
+    value.map_or("null".to_string(), |n| n.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_to_json() {
+        let mut report = SyncReport {
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        report.add_remote(RemoteReport {
+            remote: "git@github.com:org/repo.git".to_string(),
+            branch: "main".to_string(),
+            files_added: 3,
+            commit_created: true,
+            push_succeeded: true,
+            commit: Some("4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string()),
+            snapshot_size_bytes: None,
+            message: "Sync 3 files".to_string(),
+        });
+        assert_eq!(
+            report.to_json(),
+            r#"{"files_added":3,"commit_created":true,"push_succeeded":true,"commit":"4b825dc642cb6eb9a060e54bf8d69288fbee4904","snapshot_size_bytes":null,"duration_secs":1.500,"remotes":[{"remote":"git@github.com:org/repo.git","branch":"main","files_added":3,"commit_created":true,"push_succeeded":true,"commit":"4b825dc642cb6eb9a060e54bf8d69288fbee4904","snapshot_size_bytes":null}]}"#
+        );
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(json_string("tab\t"), r#""tab\u0009""#);
+    }
+}
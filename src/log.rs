@@ -0,0 +1,82 @@
+use crate::RemoteReport;
+use crate::config::SyncTarget;
+use anyhow::{Context, Result};
+use snowfall_core::strings::to_pretty_byte_size;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Append a single timestamped line describing the sync to one remote to the log file
+pub(crate) fn append_sync_log(
+    log_file: &Path,
+    target: &SyncTarget,
+    report: &RemoteReport,
+) -> Result<()> {
+    let snapshot = report
+        .snapshot_size_bytes
+        .map_or("-".to_string(), to_pretty_byte_size);
+    let line = format!(
+        "{} remote={} branch={} pushed={} snapshot={} message={:?}\n",
+        format_utc_timestamp(unix_now()),
+        target.remote,
+        target.branch,
+        report.push_succeeded,
+        snapshot,
+        report.message
+    );
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| format!("Failed to open log file: {}", log_file.display()))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Failed to write log file: {}", log_file.display()))
+}
+
+/// Seconds since the Unix epoch
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp
+pub(crate) fn format_utc_timestamp(epoch_secs: u64) -> String {
+    let days = (epoch_secs / 86_400) as i64;
+    let secs_of_day = epoch_secs % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc_timestamp() {
+        assert_eq!(format_utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc_timestamp(1_752_000_000), "2025-07-08T18:40:00Z");
+    }
+}
@@ -1,34 +1,48 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use snowfall_core::prelude::cprintln;
-use std::collections::HashSet;
+use anyhow::{Context, Result, bail};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use sea_git_sync::{OutputFormat, SyncConfig};
+use snowfall_core::prelude::{cformat, cprintln, error_ln};
+use snowfall_core::strings::{to_pretty_byte_size, to_pretty_duration};
+use std::ffi::OsString;
 use std::fs;
-use std::fs::read_link;
-use std::path::Path;
-use std::process::Command;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+/// Config file with persistent options, read from the working directory (or
+/// the `--subdir` given on the command line)
+const CONFIG_FILE: &str = ".sea-git-sync.toml";
 
 /// CLI arguments for the sync tool
 #[derive(Parser, Debug)]
 #[command(name = "🌊 sea-git-sync")]
 #[command(about = "A CLI tool to sync subdirectories from monorepos to external git repositories")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Remote repository URL
-    #[arg(long, required = true)]
-    remote: String,
-    #[arg(long, default_value = "main")]
-    branch: String,
-    #[arg(long, default_value = "Sync changes")]
-    message: String,
-    /// Copy symlinks as files instead of links
-    #[arg(long, default_value_t = true)]
-    copy_symlinks: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Sync without asking for confirmation first (required when stdin is not
+    /// a terminal)
+    #[arg(long, short)]
+    yes: bool,
+    #[command(flatten)]
+    config: SyncConfig,
 }
 
-const SNAPSHOT_FILE: &str = ".git-sync-snapshot.tar.gz";
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a shell completion script to stdout
+    Completions { shell: Shell },
+    /// Show each remote's snapshot and whether a sync has changes to commit,
+    /// without syncing
+    Status {
+        #[command(flatten)]
+        config: Box<SyncConfig>,
+    },
+}
 
 fn main() -> Result<()> {
-    let start = std::time::Instant::now();
-
     let cargo_toml = include_str!("../Cargo.toml");
     let cargo_toml: toml::Value =
         toml::from_str(cargo_toml).context("Failed to parse Cargo.toml")?;
@@ -38,279 +52,333 @@ fn main() -> Result<()> {
         .and_then(|v| v.as_str())
         .unwrap_or("unknown");
 
-    let args = Args::parse();
-    cprintln!("#39C", "🌊 [sea-git-sync](#39C) [v{}](#B4F)", version);
-    cprintln!("#39C", "{}", "[~](#39F)[~](#7AF)".repeat(32));
-    if let Err(e) = sync_to_remote(&args) {
-        eprintln!("Sync failed: {}", e);
-        std::process::exit(1);
-    }
-
-    let duration = start.elapsed().as_secs_f32();
-    println!();
-    cprintln!(
-        "#1C3",
-        "✔ Sync completed successfully! [({duration:.1}s)](#666)",
-    );
-    Ok(())
-}
-
-fn sync_to_remote(args: &Args) -> Result<()> {
-    let snapshot_path = Path::new(SNAPSHOT_FILE);
-    if !snapshot_path.exists() {
-        cprintln!("#39C", "No snapshot found, creating initial clone...");
-        create_initial_snapshot(&args.remote)?;
-    }
-
-    cprintln!("#39C", "Syncing changes to remote repository...");
-
-    let git_dir = Path::new(".git");
-    if !git_dir.exists() {
-        ensure_clean_dir(git_dir)?;
-        extract_snapshot(snapshot_path, git_dir)?;
-    }
-    // Remove the snapshot since we have an active .git directory
-    run_command("rm", &["-f", ".git-sync-snapshot.tar.gz"])?;
-    git(&["ls-files"])?;
-
-    let mut replaced_symlinks = Vec::new();
-    if args.copy_symlinks {
-        cprintln!("#39C", "Copying symlinks as files...");
-        replaced_symlinks = copy_symlinks();
-        for rep in &replaced_symlinks {
-            git(&["add", "--force", rep.symlink_path.to_str().unwrap()])?;
+    let args = parse_args()?;
+    let config = match args.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                "sea-git-sync",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
         }
+        Some(Command::Status { config }) => {
+            print_status(&config);
+            return Ok(());
+        }
+        None => args.config,
+    };
+    let show_status = config.output == OutputFormat::Human && !config.quiet;
+    if show_status {
+        cprintln!("#39C", "🌊 [sea-git-sync](#39C) [v{}](#B4F)", version);
+        cprintln!("#39C", "{}", "[~](#39F)[~](#7AF)".repeat(32));
     }
-
-    git(&["add", "."])?;
-    git(&["commit", "-m", &args.message])?;
-    git(&["pull", &args.remote, &args.branch, "--no-ff"])?;
-
-    match git(&["push", &args.remote, &args.branch]) {
-        Ok(_) => {
-            cprintln!("#39C", "Push successful, updating snapshot...");
-            git(&["gc", "--aggressive", "--prune=now"])?;
-            create_snapshot(git_dir, snapshot_path)?;
+    if !args.yes {
+        match confirm_sync(&config) {
+            Ok(true) => {}
+            Ok(false) => {
+                error_ln!("Sync cancelled");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error_ln!("{:#}", e);
+                std::process::exit(1);
+            }
         }
-        Err(_) => eprintln!("Push failed, not updating snapshot"),
     }
+    // Put back the symlinks and remove the extracted .git if the sync is
+    // interrupted partway
+    ctrlc::set_handler(|| sea_git_sync::exit_interrupted())
+        .context("Failed to install the Ctrl-C handler")?;
+    let report = match sea_git_sync::sync(&config) {
+        Ok(report) => report,
+        Err(e) => {
+            error_ln!("Sync failed: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Display the snapshot file size (since it can be abnormally large)
-    run_command("du", &["-h", ".git-sync-snapshot.tar.gz"])?;
-
-    if !replaced_symlinks.is_empty() {
-        cprintln!("#39C", "Restoring original symlinks...");
-        undo_symlink_replacements(replaced_symlinks);
+    match config.output {
+        OutputFormat::Human => {
+            if show_status {
+                println!();
+            }
+            let duration = report.duration.as_secs_f32();
+            match &report.commit {
+                Some(commit) => cprintln!(
+                    "#1C3",
+                    "✔ Sync completed successfully! [{}](key) [([{duration:.3}](duration,#666))](#666)",
+                    &commit[..commit.len().min(12)]
+                ),
+                None => cprintln!(
+                    "#1C3",
+                    "✔ Sync completed successfully! [([{duration:.3}](duration,#666))](#666)",
+                ),
+            }
+        }
+        OutputFormat::Json => println!("{}", report.to_json()),
     }
-
-    fs::remove_dir_all(git_dir).context("Failed to clean up .git directory")?;
     Ok(())
 }
 
-/// Struct to track replaced symlinks for undoing changes
-#[derive(Debug)]
-struct SymlinkReplacement {
-    symlink_path: std::path::PathBuf,
-    target: std::path::PathBuf,
-    was_dir: bool,
-}
-
-/// Replace symlinks with their target directories, returning info for undoing changes
-fn copy_symlinks() -> Vec<SymlinkReplacement> {
-    fn visit_and_replace_symlinks(
-        path: &Path,
-        replaced: &mut Vec<SymlinkReplacement>,
-        visited: &mut HashSet<std::path::PathBuf>,
-    ) {
-        let entries = match fs::read_dir(path) {
-            Ok(e) => e,
-            Err(_) => return,
-        };
-
-        for entry in entries.flatten() {
-            let entry_path = entry.path();
-            if !visited.insert(entry_path.clone()) {
-                continue;
-            }
-
-            let metadata = match entry.metadata() {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-
-            if metadata.file_type().is_symlink() {
-                let target = match read_link(&entry_path) {
-                    Ok(t) => t,
-                    Err(_) => continue,
-                };
-
-                let abs_target = if target.is_absolute() {
-                    target.clone()
-                } else {
-                    entry_path.parent().unwrap_or(Path::new(".")).join(&target)
-                };
-
-                let target_meta = match fs::metadata(&abs_target) {
-                    Ok(m) => m,
-                    Err(_) => continue,
-                };
-
-                if target_meta.is_dir() {
-                    let _ = fs::remove_file(&entry_path);
-                    let _ = copy_dir_all(&abs_target, &entry_path);
-                    let abs_target = abs_target.canonicalize().unwrap_or(abs_target);
-                    replaced.push(SymlinkReplacement {
-                        symlink_path: entry_path.clone(),
-                        target: abs_target,
-                        was_dir: true,
-                    });
-                    cprintln!("#555", "{}", entry_path.display());
-                    visit_and_replace_symlinks(&entry_path, replaced, visited);
-                }
-                continue;
-            }
-
-            if metadata.is_dir() {
-                visit_and_replace_symlinks(&entry_path, replaced, visited);
-            }
+/// Print the `status` of each remote, exiting on failure
+fn print_status(config: &SyncConfig) {
+    let status = match sea_git_sync::status(config) {
+        Ok(status) => status,
+        Err(e) => {
+            error_ln!("Status failed: {:#}", e);
+            std::process::exit(1);
         }
+    };
+    if config.output == OutputFormat::Json {
+        println!("{}", status.to_json());
+        return;
     }
 
-    fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
-        fs::create_dir_all(dst)?;
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let file_type = entry.file_type()?;
-            let src_path = entry.path();
-            let dst_path = dst.join(entry.file_name());
-            if file_type.is_dir() {
-                copy_dir_all(&src_path, &dst_path)?;
-            } else if file_type.is_file() {
-                fs::copy(&src_path, &dst_path)?;
-            }
+    for remote in &status.remotes {
+        cprintln!(
+            "#39C",
+            "[{}](filename) [{}](key)",
+            remote.remote,
+            remote.branch
+        );
+        let (Some(size), Some(changes)) = (remote.snapshot_size_bytes, &remote.changes) else {
+            cprintln!(
+                "warn",
+                "  No snapshot at [{}](filename), the next sync starts with a fresh clone",
+                remote.snapshot_path.display()
+            );
+            continue;
+        };
+        let age = remote
+            .snapshot_age
+            .map_or("?".to_string(), |age| to_pretty_duration(age.as_secs_f64()));
+        cprintln!(
+            "#39C",
+            "  Snapshot: [{}](filename) [{}](number), written [{}](#666) ago",
+            remote.snapshot_path.display(),
+            to_pretty_byte_size(size),
+            age
+        );
+        if let Some(commit) = &remote.commit {
+            cprintln!(
+                "#39C",
+                "  Last synced commit: [{}](key)",
+                &commit[..commit.len().min(12)]
+            );
+        }
+        if remote.needs_sync() {
+            cprintln!(
+                "warn",
+                "  Changes to sync: [{}](number) added, [{}](number) modified, [{}](number) deleted",
+                changes.added,
+                changes.modified,
+                changes.deleted
+            );
+        } else {
+            cprintln!("#1C3", "  ✔ Up to date with the snapshot");
         }
-        Ok(())
     }
-
-    let mut replaced = Vec::new();
-    let mut visited = HashSet::new();
-    visit_and_replace_symlinks(Path::new("."), &mut replaced, &mut visited);
-    replaced
 }
 
-/// Undo the symlink replacements, restoring the original symlinks
-fn undo_symlink_replacements(replacements: Vec<SymlinkReplacement>) {
-    for rep in replacements {
-        let _ = fs::remove_dir_all(&rep.symlink_path);
-        cprintln!("#555", "{}", rep.symlink_path.display());
-        let _ = std::os::unix::fs::symlink(&rep.target, &rep.symlink_path);
+/// Ask on the terminal whether to go ahead, after summarizing where the sync
+/// will run and push to. The sync replaces symlinks and the `.git` directory
+/// in the working directory, so running it in the wrong place does damage.
+/// Without a terminal to ask on, this fails unless `--yes` was given.
+fn confirm_sync(config: &SyncConfig) -> Result<bool> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        bail!(
+            "Refusing to sync without confirmation as stdin is not a terminal; pass --yes to sync anyway"
+        );
     }
-}
 
-/// Create initial snapshot by cloning the remote repository
-fn create_initial_snapshot(remote_url: &str) -> Result<()> {
-    let temp_dir = Path::new("git-remote");
-    ensure_clean_dir(temp_dir)?;
+    let mut dir = std::env::current_dir().context("Failed to read current directory")?;
+    if let Some(subdir) = &config.subdir {
+        dir = dir.join(subdir);
+    }
+    eprintln!(
+        "{}",
+        cformat(
+            "#39C",
+            &format!("Working directory: [{}](filename)", dir.display())
+        )
+    );
+    for (i, remote) in config.remotes.iter().enumerate() {
+        // The source branch is only detected once the sync starts
+        let branch = match &config.branch_prefix {
+            Some(prefix) => format!("{}<current branch>", prefix),
+            None => config.branches[i.min(config.branches.len().saturating_sub(1))].clone(),
+        };
+        eprintln!(
+            "{}",
+            cformat(
+                "#39C",
+                &format!("Remote: [{}](filename) [{}](key)", remote, branch)
+            )
+        );
+    }
+    eprint!(
+        "{}",
+        cformat("warn", "Replace symlinks and .git here and sync? [y/N] ")
+    );
+    std::io::stderr().flush()?;
 
-    run_command_in_dir("git", &["clone", remote_url, "."], temp_dir)?;
-    create_snapshot(&temp_dir.join(".git"), Path::new(SNAPSHOT_FILE))?;
-    fs::remove_dir_all(temp_dir)?;
-    Ok(())
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+    Ok(is_yes(&answer))
 }
 
-/// Extract git snapshot to target directory
-fn extract_snapshot(snapshot_path: &Path, target_dir: &Path) -> Result<()> {
-    run_command(
-        "tar",
-        &[
-            "-xzf",
-            path_str(snapshot_path)?,
-            "-C",
-            path_str(target_dir)?,
-            "--strip-components=1",
-        ],
-    )
+/// Whether a prompt answer agrees, anything else counting as no
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
 }
 
-/// Create compressed snapshot of git directory
-fn create_snapshot(git_dir: &Path, snapshot_path: &Path) -> Result<()> {
-    let parent = git_dir.parent().context("git directory has no parent")?;
-    let name = git_dir
-        .file_name()
-        .context("Git directory has no name")?
-        .to_str()
-        .context("Invalid git directory name")?;
-
-    let mut args = vec!["-czf", path_str(snapshot_path)?];
-    if !parent.display().to_string().is_empty() {
-        args.extend(vec!["-C", path_str(parent)?]);
+/// Parse the command line, filling in any options it leaves unset from the
+/// config file. Command-line flags take precedence over the config file,
+/// which takes precedence over the built-in defaults.
+fn parse_args() -> Result<Args> {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let command = Args::command();
+    let matches = command
+        .clone()
+        .try_get_matches_from(&cli)
+        .unwrap_or_else(|e| e.exit());
+
+    // `status` takes the same options as a sync, so the file applies to it too
+    let (options, option_matches) = match matches.subcommand() {
+        None => (&command, &matches),
+        Some(("status", status_matches)) => {
+            (command.find_subcommand("status").unwrap(), status_matches)
+        }
+        Some(_) => return Ok(Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())),
+    };
+    let dir = option_matches.get_one::<PathBuf>("subdir").cloned();
+    let path = dir.unwrap_or_default().join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()));
     }
-    args.push(name);
-    run_command("tar", &args)
-}
 
-// Helper functions
-
-/// Run a git command with standard error handling
-fn git(args: &[&str]) -> Result<()> {
-    cprintln!("555", "> [git {}](goldenrod)", args.join(" "));
-    let status = Command::new("git")
-        .args(args)
-        .status()
-        .context("Failed to execute git command")?;
-    if !status.success() {
-        let exit_code = status.code().unwrap_or(-1);
-
-        // For git commit, exit code 1 with no staged changes is acceptable
-        if args[0] == "commit" && exit_code == 1 {
-            println!("No changes to commit");
-            return Ok(());
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    let table: toml::Table = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+    // Turn each file entry into the equivalent flags, placed before the real
+    // command line's options (after the subcommand, if any) and skipped
+    // entirely when the command line sets that option
+    let split = match matches.subcommand_name() {
+        Some(name) => cli.iter().position(|arg| arg == name).unwrap_or(0) + 1,
+        None => 1,
+    };
+    let mut args = cli[..split].to_vec();
+    for (key, value) in &table {
+        let long = key.replace('_', "-");
+        let Some(arg) = options.get_arguments().find(|arg| has_long(arg, &long)) else {
+            // Options that only apply to a sync, like `yes`, don't affect `status`
+            if command.get_arguments().any(|arg| has_long(arg, &long)) {
+                continue;
+            }
+            bail!("Unknown option `{}` in {}", key, path.display());
+        };
+        if option_matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
         }
-
-        return Err(anyhow::anyhow!(
-            "Git command failed with exit code: {}",
-            exit_code
-        ));
+        let flags = config_file_flags(&long, arg.get_action().takes_values(), value)
+            .with_context(|| format!("Invalid value for `{}` in {}", key, path.display()))?;
+        args.extend(flags);
     }
-    Ok(())
+    args.extend(cli.into_iter().skip(split));
+    Ok(Args::try_parse_from(args).unwrap_or_else(|e| e.exit()))
 }
 
-/// Run any command with error handling
-fn run_command(cmd: &str, args: &[&str]) -> Result<()> {
-    run_command_in_dir(cmd, args, Path::new("."))
+/// Whether `--<long>` names the argument, by its name or an alias
+fn has_long(arg: &clap::Arg, long: &str) -> bool {
+    arg.get_long_and_visible_aliases()
+        .is_some_and(|names| names.contains(&long))
 }
 
-/// Run command in specific directory
-fn run_command_in_dir(cmd: &str, args: &[&str], dir: &Path) -> Result<()> {
-    cprintln!("555", "> [{} {}](goldenrod)", cmd, args.join(" "));
-    let status = Command::new(cmd)
-        .args(args)
-        .current_dir(dir)
-        .status()
-        .with_context(|| format!("Failed to execute {} command", cmd))?;
-
-    if !status.success() {
-        return Err(anyhow::anyhow!(
-            "Command failed with exit code: {}",
-            status.code().unwrap_or(-1)
-        ));
+/// The command-line flags equivalent to a config file entry. Arrays repeat the
+/// flag, and `true`/`false` turn plain on/off flags on or leave them off.
+fn config_file_flags(long: &str, takes_values: bool, value: &toml::Value) -> Result<Vec<OsString>> {
+    let flag = |value: &dyn std::fmt::Display| OsString::from(format!("--{}={}", long, value));
+    match value {
+        toml::Value::Boolean(true) if !takes_values => Ok(vec![format!("--{}", long).into()]),
+        toml::Value::Boolean(false) if !takes_values => Ok(vec![]),
+        toml::Value::Boolean(b) => Ok(vec![flag(b)]),
+        toml::Value::String(s) => Ok(vec![flag(s)]),
+        toml::Value::Integer(n) => Ok(vec![flag(n)]),
+        toml::Value::Array(items) => {
+            let mut flags = Vec::new();
+            for item in items {
+                if item.is_array() {
+                    bail!("nested arrays are not supported");
+                }
+                flags.extend(config_file_flags(long, takes_values, item)?);
+            }
+            Ok(flags)
+        }
+        _ => bail!("expected a string, integer, boolean, or array"),
     }
-    Ok(())
 }
 
-/// Ensure directory exists and is empty
-fn ensure_clean_dir(dir: &Path) -> Result<()> {
-    if dir.exists() {
-        fs::remove_dir_all(dir)
-            .with_context(|| format!("Failed to remove existing directory: {}", dir.display()))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workdir_is_subdir_alias() {
+        let args =
+            Args::try_parse_from(["sea-git-sync", "--workdir", "a/b", "--remote", "r"]).unwrap();
+        assert_eq!(args.config.subdir, Some(PathBuf::from("a/b")));
+
+        let command = Args::command();
+        let subdir = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "subdir")
+            .unwrap();
+        assert!(has_long(subdir, "workdir"));
+        assert!(has_long(subdir, "subdir"));
+        assert!(!has_long(subdir, "dir"));
+    }
+
+    #[test]
+    fn test_is_yes() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" Yes "));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("no"));
+        assert!(!is_yes("yep"));
     }
-    fs::create_dir_all(dir)
-        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
-    Ok(())
-}
 
-/// Convert Path to &str with proper error handling
-fn path_str(path: &Path) -> Result<&str> {
-    path.to_str()
-        .with_context(|| format!("Invalid path: {}", path.display()))
+    #[test]
+    fn test_config_file_flags() {
+        let remotes = toml::Value::Array(vec!["a:x".into(), "b:y".into()]);
+        assert_eq!(
+            config_file_flags("remote", true, &remotes).unwrap(),
+            vec![
+                OsString::from("--remote=a:x"),
+                OsString::from("--remote=b:y")
+            ]
+        );
+        assert_eq!(
+            config_file_flags("retries", true, &toml::Value::Integer(3)).unwrap(),
+            vec![OsString::from("--retries=3")]
+        );
+        assert_eq!(
+            config_file_flags("squash", false, &toml::Value::Boolean(true)).unwrap(),
+            vec![OsString::from("--squash")]
+        );
+        assert!(
+            config_file_flags("squash", false, &toml::Value::Boolean(false))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            config_file_flags("copy-symlinks", true, &toml::Value::Boolean(false)).unwrap(),
+            vec![OsString::from("--copy-symlinks=false")]
+        );
+        assert!(config_file_flags("message", true, &toml::Value::Float(1.5)).is_err());
+    }
 }
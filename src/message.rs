@@ -0,0 +1,79 @@
+use crate::git::git_output;
+use regex::Regex;
+
+/// Placeholders that `--message` may contain
+const PLACEHOLDERS: &[&str] = &["{date}", "{count}", "{source_sha}"];
+
+/// Values substituted into the commit message placeholders
+pub(crate) struct MessageValues<'a> {
+    /// Date of the sync, as `YYYY-MM-DD` in UTC
+    pub(crate) date: &'a str,
+    /// Number of changed files in the sync commit
+    pub(crate) count: usize,
+    /// HEAD of the enclosing monorepo
+    pub(crate) source_sha: &'a str,
+}
+
+/// The commit message with its placeholders filled in. Anything else in
+/// braces is left as written.
+pub(crate) fn expand_message(template: &str, values: &MessageValues) -> String {
+    template
+        .replace("{date}", values.date)
+        .replace("{count}", &values.count.to_string())
+        .replace("{source_sha}", values.source_sha)
+}
+
+/// Whether the message uses the `{source_sha}` placeholder, which needs an
+/// extra git call to fill in
+pub(crate) fn uses_source_sha(template: &str) -> bool {
+    template.contains("{source_sha}")
+}
+
+/// HEAD of the monorepo enclosing the synced directory (whose own `.git` is
+/// the mirror's), or `unknown` outside of a repository
+pub(crate) fn source_sha() -> String {
+    git_output(&["-C", "..", "rev-parse", "HEAD"])
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Whether a commit subject could have come from the first line of the
+/// message template, with any values in place of its placeholders
+pub(crate) fn matches_template(template: &str, subject: &str) -> bool {
+    let first_line = template.lines().next().unwrap_or_default().trim();
+    let mut pattern = regex::escape(first_line);
+    for placeholder in PLACEHOLDERS {
+        pattern = pattern.replace(&regex::escape(placeholder), ".*");
+    }
+    Regex::new(&format!("^{}$", pattern)).is_ok_and(|re| re.is_match(subject.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_message() {
+        let values = MessageValues {
+            date: "2025-07-08",
+            count: 3,
+            source_sha: "4b825dc",
+        };
+        assert_eq!(
+            expand_message(
+                "Sync {count} files from {source_sha} on {date} {other}",
+                &values
+            ),
+            "Sync 3 files from 4b825dc on 2025-07-08 {other}"
+        );
+    }
+
+    #[test]
+    fn test_matches_template() {
+        let template = "Sync {count} files from {source_sha}\n\nDetails";
+        assert!(matches_template(template, "Sync 3 files from 4b825dc"));
+        assert!(!matches_template(template, "Fix typo"));
+        assert!(matches_template("Sync (v1.0)", "Sync (v1.0)"));
+        assert!(!matches_template("Sync (v1.0)", "Sync v1x0"));
+    }
+}
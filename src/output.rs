@@ -0,0 +1,63 @@
+use crate::OutputFormat;
+use std::process::Stdio;
+use std::sync::{PoisonError, RwLock};
+
+/// Print a colorized status line, unless running quietly or stdout is
+/// reserved for JSON output
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::show_status() {
+            ::snowfall_core::cprintln!($($arg)*);
+        }
+    };
+}
+
+/// How much status output to print in human-readable mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Verbosity {
+    Quiet,
+    Normal,
+    /// Also how long each command took and the executable it ran
+    Verbose,
+    /// Also the environment variables set for each command
+    Trace,
+}
+
+/// Output format for the current run, set at the start of each sync
+static OUTPUT_FORMAT: RwLock<OutputFormat> = RwLock::new(OutputFormat::Human);
+
+/// Verbosity for the current run, set at the start of each sync
+static VERBOSITY: RwLock<Verbosity> = RwLock::new(Verbosity::Normal);
+
+/// Set the output format and verbosity used by subsequent status output
+pub(crate) fn configure(format: OutputFormat, verbosity: Verbosity) {
+    *OUTPUT_FORMAT
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = format;
+    *VERBOSITY.write().unwrap_or_else(PoisonError::into_inner) = verbosity;
+}
+
+/// The configured output format, defaulting to human-readable
+pub(crate) fn output_format() -> OutputFormat {
+    *OUTPUT_FORMAT.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The configured verbosity, defaulting to normal
+pub(crate) fn verbosity() -> Verbosity {
+    *VERBOSITY.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether status lines and command echoes should be printed
+pub(crate) fn show_status() -> bool {
+    output_format() == OutputFormat::Human && verbosity() >= Verbosity::Normal
+}
+
+/// Where child processes should write their stdout: the terminal, stderr
+/// when stdout is reserved for JSON output, or nowhere when running quietly
+pub(crate) fn child_stdout() -> Stdio {
+    match (output_format(), verbosity()) {
+        (OutputFormat::Json, _) => std::io::stderr().into(),
+        (OutputFormat::Human, Verbosity::Quiet) => Stdio::null(),
+        (OutputFormat::Human, _) => Stdio::inherit(),
+    }
+}
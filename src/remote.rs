@@ -0,0 +1,383 @@
+use crate::config::SyncTarget;
+use crate::git::{TransferStats, git, git_output, git_stderr, has_own_git_dir};
+use crate::message::matches_template;
+use crate::{MergeStrategy, SyncConfig};
+use anyhow::{Context, Result};
+use regex::Regex;
+use snowfall_core::strings::{to_comma_string, to_pretty_byte_size};
+use std::path::Path;
+
+/// Check that the remote looks like a URL git can reach, so a typo fails
+/// up front instead of deep inside `git clone`. Supported forms are
+/// `https://`, `http://`, `ssh://`, `git://`, scp-style `user@host:path`,
+/// and `file://` URLs or local paths, which must exist.
+pub(crate) fn validate_remote(remote: &str) -> Result<()> {
+    let invalid = |reason: &str| anyhow::anyhow!("Invalid remote \"{}\": {}", remote, reason);
+    if remote.trim().is_empty() {
+        return Err(invalid("the remote is empty"));
+    }
+    if remote.chars().any(char::is_whitespace) {
+        return Err(invalid("URLs cannot contain whitespace"));
+    }
+
+    if let Some(path) = remote.strip_prefix("file://") {
+        if !Path::new(path).exists() {
+            return Err(invalid("the path does not exist"));
+        }
+        return Ok(());
+    }
+    if let Some((scheme, rest)) = remote.split_once("://") {
+        if !["https", "http", "ssh", "git"].contains(&scheme) {
+            return Err(invalid(
+                "unsupported scheme, expected https://, ssh://, git://, or file://",
+            ));
+        }
+        let host = rest.split('/').next().unwrap_or_default();
+        let host = host.rsplit('@').next().unwrap_or_default();
+        if host.is_empty() || host.starts_with(':') {
+            return Err(invalid("the URL has no host"));
+        }
+        return Ok(());
+    }
+
+    let scp_like = Regex::new(r"^(?:[^@/:]+@)?[A-Za-z0-9.-]+:[^/].*$").unwrap();
+    if scp_like.is_match(remote) || Path::new(remote).exists() {
+        return Ok(());
+    }
+    Err(invalid(
+        "expected https://, ssh://, git@host:path, file://, or an existing local path",
+    ))
+}
+
+/// Whether the remote is the name of a git remote (like `origin`) rather than
+/// a URL or local path. URLs contain `://` or `@`, scp-style `host:path`
+/// remotes a `:`, which git doesn't allow in remote names.
+pub(crate) fn is_remote_name(remote: &str) -> bool {
+    !remote.is_empty()
+        && !remote.contains("://")
+        && !remote.contains(['@', ':'])
+        && !Path::new(remote).exists()
+}
+
+/// The URL of the remote, resolving a remote name with `git remote get-url`.
+/// The name is looked up in the `.git` git finds from the working directory:
+/// the one kept from an earlier sync, or otherwise the monorepo's own.
+pub(crate) fn resolve_remote(remote: &str) -> Result<String> {
+    if !is_remote_name(remote) {
+        return Ok(remote.to_string());
+    }
+    let url = git_output(&["remote", "get-url", remote])
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .with_context(|| {
+            format!(
+                "Remote \"{}\" is not a URL and no git remote of that name exists; \
+                 pass the URL, or add it with `git remote add {} <url>`",
+                remote, remote
+            )
+        })?;
+    status!(
+        "#39C",
+        "Resolved remote [{}](key) to [{}](filename)",
+        remote,
+        url
+    );
+    Ok(url)
+}
+
+/// The remote whose history the `.git` directory holds, as recorded by
+/// [`claim_git_dir`], if there is a `.git` directory
+pub(crate) fn git_dir_owner() -> Option<String> {
+    if !has_own_git_dir() {
+        return None;
+    }
+    git_output(&["config", "--get", "sea-git-sync.remote"])
+        .ok()
+        .map(|owner| owner.trim().to_string())
+}
+
+/// Record in `.git/config` which remote the `.git` directory holds the history
+/// of, failing if it was left behind by a sync to a different remote (such as
+/// after a pull that needs its merge resolved by hand)
+pub(crate) fn claim_git_dir(remote: &str) -> Result<()> {
+    match git_dir_owner() {
+        Some(owner) if owner != remote => Err(anyhow::anyhow!(
+            "The .git directory holds the history of {}, not {}; finish that sync or remove .git first",
+            owner,
+            remote
+        )),
+        Some(_) => Ok(()),
+        None => {
+            git(&["config", "sea-git-sync.remote", remote])?;
+            Ok(())
+        }
+    }
+}
+
+/// Whether the remote branch already points at the local HEAD, so there is
+/// nothing to pull or push. Any failure to tell counts as not up to date.
+pub(crate) fn remote_is_at_head(target: &SyncTarget) -> bool {
+    let branch = format!("refs/heads/{}", target.branch);
+    let Ok(remote) = git_output(&["ls-remote", target.remote, &branch]) else {
+        return false;
+    };
+    let Ok(head) = git_output(&["rev-parse", "HEAD"]) else {
+        return false;
+    };
+    remote
+        .split_whitespace()
+        .next()
+        .is_some_and(|sha| sha == head.trim())
+}
+
+/// Whether the branch has to be created on the remote, failing up front if it
+/// doesn't exist and `--branch-create` is not set. A remote that can't be
+/// reached counts as having the branch, leaving the error to the pull.
+pub(crate) fn branch_needs_creating(config: &SyncConfig, target: &SyncTarget) -> Result<bool> {
+    if config.force_push {
+        return Ok(false);
+    }
+    let branch = format!("refs/heads/{}", target.branch);
+    let missing = git_output(&["ls-remote", "--heads", target.remote, &branch])
+        .is_ok_and(|refs| refs.trim().is_empty());
+    if missing && !config.branch_create {
+        return Err(anyhow::anyhow!(
+            "Branch {} does not exist on {}; pass --branch-create to create it",
+            target.branch,
+            target.remote
+        ));
+    }
+    Ok(missing)
+}
+
+/// Pull from and push to the remote, retrying both steps with exponential
+/// backoff. Returns whether the push succeeded; a pull that still fails once
+/// retries run out is returned as an error, and if it stopped on a conflict the
+/// merge is left to be resolved by hand.
+///
+/// With `--force-push` the pull is skipped and the push overwrites the remote.
+/// When `squashed_over` is the previous sync commit that HEAD amended, the pull
+/// is skipped too and the push only replaces the remote branch if it still
+/// points at that commit.
+///
+/// With `create_branch` there is nothing to pull, and the branch is created
+/// on the remote from HEAD.
+pub(crate) fn pull_and_push(
+    config: &SyncConfig,
+    target: &SyncTarget,
+    squashed_over: Option<&str>,
+    create_branch: bool,
+) -> Result<bool> {
+    if create_branch {
+        status!(
+            "#39C",
+            "Creating branch [{}](key) on [{}](filename)",
+            target.branch,
+            target.remote
+        );
+    }
+
+    if config.force_push {
+        status!(
+            "warn",
+            "⚠ Force-pushing to [{}](filename) [{}](key): remote history not in the monorepo will be lost!",
+            target.remote,
+            target.branch
+        );
+    }
+
+    let lease = squashed_over.map(|sha| format!("--force-with-lease={}:{}", target.branch, sha));
+    let new_branch = format!("HEAD:refs/heads/{}", target.branch);
+    // Without a terminal, git only reports what it sent when asked to
+    let mut push_args = vec!["push", "--progress"];
+    if create_branch {
+        push_args.extend(["--set-upstream", target.remote, &new_branch]);
+    } else {
+        if config.force_push {
+            push_args.push("--force");
+        } else if let Some(lease) = &lease {
+            push_args.push(lease);
+        }
+        push_args.extend([target.remote, target.branch]);
+    }
+    let skip_pull = config.force_push || squashed_over.is_some() || create_branch;
+
+    let mut attempt = 0;
+    loop {
+        let pulled = if skip_pull {
+            Ok(())
+        } else {
+            pull(config, target)
+        };
+        let result = match pulled {
+            Ok(_) => git_stderr(&push_args),
+            // Pulling again can't get past a conflict, so report it right away
+            Err(e) if attempt >= config.retries || merge_in_progress(Path::new(".git")) => {
+                return Err(e);
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(stderr) => {
+                if let Some(stats) = TransferStats::parse(&stderr) {
+                    status!(
+                        "#39C",
+                        "Pushed [{}](number) objects, [{}](number)",
+                        to_comma_string(stats.objects),
+                        to_pretty_byte_size(stats.bytes)
+                    );
+                }
+                return Ok(true);
+            }
+            Err(_) if attempt >= config.retries => return Ok(false),
+            Err(e) => {
+                let delay = config.retry_delay.saturating_mul(1 << attempt.min(16));
+                attempt += 1;
+                status!(
+                    "warn",
+                    "{}, retrying in {}s (attempt {} of {})...",
+                    e,
+                    delay,
+                    attempt,
+                    config.retries
+                );
+                std::thread::sleep(std::time::Duration::from_secs(delay));
+            }
+        }
+    }
+}
+
+/// The hash of HEAD if it is a previous sync commit (same message, with any
+/// placeholder values) that can be amended by `--squash`
+pub(crate) fn squashable_head(config: &SyncConfig) -> Result<Option<String>> {
+    // An empty repository has no HEAD to amend
+    let Ok(subject) = git_output(&["log", "-1", "--format=%s"]) else {
+        return Ok(None);
+    };
+    if !matches_template(&config.message, &subject) {
+        return Ok(None);
+    }
+    Ok(Some(git_output(&["rev-parse", "HEAD"])?.trim().to_string()))
+}
+
+/// Pull the remote branch using the configured merge strategy
+fn pull(config: &SyncConfig, target: &SyncTarget) -> Result<()> {
+    let flag = config.merge_strategy.pull_flag();
+    match git(&["pull", target.remote, target.branch, flag]) {
+        Ok(_) => Ok(()),
+        Err(e) if config.merge_strategy == MergeStrategy::FfOnly && is_not_fast_forward(&e) => {
+            Err(e.context(format!(
+                "{} has changes that cannot be fast-forwarded; re-run with \
+                 --merge-strategy merge or rebase to integrate them, or \
+                 --force-push to overwrite them",
+                target.branch
+            )))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether a failed `git pull --ff-only` failed because the branches have
+/// diverged, rather than for any other reason such as a network error
+fn is_not_fast_forward(error: &anyhow::Error) -> bool {
+    let message = format!("{:#}", error);
+    [
+        "Not possible to fast-forward",
+        "diverging branches",
+        "divergent branches",
+    ]
+    .iter()
+    .any(|reason| message.contains(reason))
+}
+
+/// Whether a pull stopped partway through a merge or rebase, leaving it to
+/// be resolved by hand in `git_dir`'s working tree
+pub(crate) fn merge_in_progress(git_dir: &Path) -> bool {
+    git_output(&["rev-parse", "-q", "--verify", "MERGE_HEAD"]).is_ok()
+        || git_dir.join("rebase-merge").exists()
+        || git_dir.join("rebase-apply").exists()
+}
+
+/// Fail before pushing anything if the tag already exists and `--force-tag` is not set
+pub(crate) fn ensure_tag_available(
+    config: &SyncConfig,
+    target: &SyncTarget,
+    tag: &str,
+) -> Result<()> {
+    if config.force_tag {
+        return Ok(());
+    }
+    let tag_ref = format!("refs/tags/{}", tag);
+    let exists_locally = git_output(&["tag", "--list", tag])?
+        .lines()
+        .any(|l| l == tag);
+    let exists_remotely = !git_output(&["ls-remote", "--tags", target.remote, &tag_ref])?
+        .trim()
+        .is_empty();
+    if exists_locally || exists_remotely {
+        return Err(anyhow::anyhow!(
+            "Tag {} already exists, use --force-tag to replace it",
+            tag
+        ));
+    }
+    Ok(())
+}
+
+/// Create an annotated tag on HEAD and push it to the remote
+pub(crate) fn push_tag(config: &SyncConfig, target: &SyncTarget, tag: &str) -> Result<()> {
+    let message = config.tag_message.as_deref().unwrap_or(tag);
+    let tag_ref = format!("refs/tags/{}", tag);
+    if config.force_tag {
+        git(&["tag", "-a", "-f", tag, "-m", message])?;
+        git(&["push", "--force", target.remote, &tag_ref])?;
+    } else {
+        git(&["tag", "-a", tag, "-m", message])?;
+        git(&["push", target.remote, &tag_ref])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_remote() {
+        assert!(validate_remote("https://github.com/org/repo.git").is_ok());
+        assert!(validate_remote("ssh://git@github.com/org/repo.git").is_ok());
+        assert!(validate_remote("git@github.com:org/repo.git").is_ok());
+        assert!(validate_remote(".").is_ok());
+
+        assert!(validate_remote("").is_err());
+        assert!(validate_remote("htps://github.com/org/repo.git").is_err());
+        assert!(validate_remote("https:///org/repo.git").is_err());
+        assert!(validate_remote("git@github.com org/repo.git").is_err());
+        assert!(validate_remote("file:///does/not/exist").is_err());
+        assert!(validate_remote("no/such/directory").is_err());
+    }
+
+    #[test]
+    fn test_is_not_fast_forward() {
+        let diverged = anyhow::anyhow!("fatal: Not possible to fast-forward, aborting.")
+            .context("Git command failed with exit code: 128");
+        assert!(is_not_fast_forward(&diverged));
+        let unreachable = anyhow::anyhow!(
+            "fatal: unable to access 'https://github.com/org/repo.git/': Could not resolve host: github.com"
+        )
+        .context("Git command failed with exit code: 128");
+        assert!(!is_not_fast_forward(&unreachable));
+    }
+
+    #[test]
+    fn test_is_remote_name() {
+        assert!(is_remote_name("origin"));
+        assert!(is_remote_name("mirror-github"));
+
+        assert!(!is_remote_name(""));
+        assert!(!is_remote_name("."));
+        assert!(!is_remote_name("https://github.com/org/repo.git"));
+        assert!(!is_remote_name("git@github.com:org/repo.git"));
+        assert!(!is_remote_name("github.com:org/repo.git"));
+    }
+}
@@ -0,0 +1,587 @@
+use crate::ensure_clean_dir;
+use crate::git::{git_binary, run_command_in_dir};
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use snowfall_core::strings::{ByteBase, to_pretty_byte_size, to_pretty_byte_size_with};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Create initial snapshot by cloning the remote repository.
+///
+/// With a `depth`, the clone is shallow. Later runs still `git pull` without a
+/// depth: the fetch stops at commits the snapshot already has, so the merge base
+/// with the remote is always present and pushes keep working. If the remote
+/// history is ever rewritten past the shallow boundary, run
+/// `git fetch --unshallow` or delete the snapshot to start over.
+pub(crate) fn create_initial_snapshot(
+    remote_url: &str,
+    snapshot_path: &Path,
+    depth: Option<u32>,
+    compression: Compression,
+) -> Result<()> {
+    let temp_dir = Path::new("git-remote");
+    ensure_clean_dir(temp_dir)?;
+
+    let depth = depth.map(|n| n.to_string());
+    let mut clone_args = vec!["clone"];
+    if let Some(depth) = &depth {
+        clone_args.extend(["--depth", depth.as_str()]);
+    }
+    clone_args.extend([remote_url, "."]);
+    run_command_in_dir(&git_binary(), &clone_args, temp_dir)?;
+    create_snapshot(&temp_dir.join(".git"), snapshot_path, None, compression)?.keep();
+    fs::remove_dir_all(temp_dir)?;
+    Ok(())
+}
+
+/// Extract git snapshot to target directory, stripping the leading `.git/`
+/// component from each archived path
+pub(crate) fn extract_snapshot(snapshot_path: &Path, target_dir: &Path) -> Result<()> {
+    verify_snapshot_checksum(snapshot_path)?;
+
+    let file = fs::File::open(snapshot_path)
+        .with_context(|| format!("Failed to open snapshot: {}", snapshot_path.display()))?;
+    let mut archive = tar::Archive::new(Progress::new(GzDecoder::new(file), "Extracted"));
+
+    for entry in archive.entries().context("Failed to read snapshot")? {
+        let mut entry = entry.context("Failed to read snapshot entry")?;
+        let path = entry.path().context("Invalid path in snapshot")?;
+        let stripped: PathBuf = path.components().skip(1).collect();
+        if stripped.as_os_str().is_empty() {
+            continue;
+        }
+        if stripped
+            .components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!(
+                "Refusing to extract unsafe path from snapshot: {}",
+                stripped.display()
+            ));
+        }
+
+        let dest = target_dir.join(&stripped);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        entry
+            .unpack(&dest)
+            .with_context(|| format!("Failed to extract: {}", dest.display()))?;
+    }
+    archive.into_inner().finish();
+    Ok(())
+}
+
+/// Create compressed snapshot of git directory, gzipped at the `compression`
+/// level. The archive is written next to
+/// `snapshot_path` first and only replaces the previous snapshot once complete
+/// and within `max_size` bytes.
+///
+/// The previous snapshot is moved aside rather than deleted, and comes back
+/// when the returned backup is dropped, unless the new snapshot is kept with
+/// [`SnapshotBackup::keep`].
+pub(crate) fn create_snapshot(
+    git_dir: &Path,
+    snapshot_path: &Path,
+    max_size: Option<u64>,
+    compression: Compression,
+) -> Result<SnapshotBackup> {
+    let partial_path = temp_path(snapshot_path);
+
+    let written = write_snapshot_archive(git_dir, &partial_path, compression).and_then(|()| {
+        let size = fs::metadata(&partial_path)
+            .with_context(|| format!("Failed to read snapshot: {}", partial_path.display()))?
+            .len();
+        check_snapshot_size(size, max_size)
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+
+    let backup = SnapshotBackup::new(snapshot_path)?;
+    fs::rename(&partial_path, snapshot_path)
+        .with_context(|| format!("Failed to replace snapshot: {}", snapshot_path.display()))?;
+    write_snapshot_checksum(snapshot_path)?;
+    Ok(backup)
+}
+
+/// The previous snapshot and its checksum, moved to `<snapshot>.bak` while a
+/// new snapshot is put in place. Dropping this restores them, so a sync that
+/// fails after the snapshot was replaced leaves the last known-good one.
+pub(crate) struct SnapshotBackup {
+    snapshot_path: PathBuf,
+    has_backup: bool,
+    kept: bool,
+}
+
+impl SnapshotBackup {
+    /// Move the current snapshot (if any) and its checksum aside
+    fn new(snapshot_path: &Path) -> Result<Self> {
+        let has_backup = snapshot_path.exists();
+        for path in [snapshot_path.to_path_buf(), checksum_path(snapshot_path)] {
+            if path.exists() {
+                let backup = backup_path(&path);
+                fs::rename(&path, &backup).with_context(|| {
+                    format!("Failed to back up snapshot to {}", backup.display())
+                })?;
+            }
+        }
+        Ok(Self {
+            snapshot_path: snapshot_path.to_path_buf(),
+            has_backup,
+            kept: false,
+        })
+    }
+
+    /// Keep the new snapshot, removing the backup of the previous one
+    pub(crate) fn keep(mut self) {
+        self.kept = true;
+        for path in [
+            self.snapshot_path.clone(),
+            checksum_path(&self.snapshot_path),
+        ] {
+            let _ = fs::remove_file(backup_path(&path));
+        }
+    }
+}
+
+impl Drop for SnapshotBackup {
+    fn drop(&mut self) {
+        if self.kept || !self.has_backup {
+            return;
+        }
+        status!(
+            "warn",
+            "Restoring previous snapshot [{}](filename)",
+            self.snapshot_path.display()
+        );
+        // The previous snapshot may predate checksums, so never leave the new one's behind
+        let checksum = checksum_path(&self.snapshot_path);
+        let _ = fs::remove_file(&checksum);
+        for path in [self.snapshot_path.clone(), checksum] {
+            let backup = backup_path(&path);
+            if backup.exists() {
+                let _ = fs::rename(&backup, &path);
+            }
+        }
+    }
+}
+
+/// Clean up after a sync that was killed while writing the snapshot: remove
+/// the partly written archive, and put the previous snapshot back if it had
+/// already been moved aside. A new snapshot that was already in place is
+/// kept, as it is only written once its push has succeeded.
+pub(crate) fn recover_interrupted_snapshot(snapshot_path: &Path) -> Result<()> {
+    // Earlier versions wrote the new snapshot to `<snapshot>.partial`
+    for partial_path in [
+        temp_path(snapshot_path),
+        with_suffix(snapshot_path, ".partial"),
+    ] {
+        if partial_path.exists() {
+            status!(
+                "warn",
+                "Removing partly written snapshot [{}](filename) from an interrupted sync",
+                partial_path.display()
+            );
+            fs::remove_file(&partial_path)
+                .with_context(|| format!("Failed to remove: {}", partial_path.display()))?;
+        }
+    }
+
+    let paths = [snapshot_path.to_path_buf(), checksum_path(snapshot_path)];
+    if !snapshot_path.exists() && backup_path(snapshot_path).exists() {
+        status!(
+            "warn",
+            "Restoring snapshot [{}](filename) from an interrupted sync",
+            snapshot_path.display()
+        );
+        for path in &paths {
+            let backup = backup_path(path);
+            if backup.exists() && !path.exists() {
+                fs::rename(&backup, path)
+                    .with_context(|| format!("Failed to restore: {}", path.display()))?;
+            }
+        }
+    }
+    for path in &paths {
+        let backup = backup_path(path);
+        if backup.exists() {
+            fs::remove_file(&backup)
+                .with_context(|| format!("Failed to remove: {}", backup.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Every file kept alongside the snapshot, including the ones that only exist
+/// while a new snapshot is being written
+pub(crate) fn snapshot_files(snapshot_path: &Path) -> Vec<PathBuf> {
+    let checksum = checksum_path(snapshot_path);
+    vec![
+        temp_path(snapshot_path),
+        backup_path(snapshot_path),
+        backup_path(&checksum),
+        snapshot_path.to_path_buf(),
+        checksum,
+    ]
+}
+
+/// Where a new snapshot is written, to be renamed into place once complete
+fn temp_path(snapshot_path: &Path) -> PathBuf {
+    with_suffix(snapshot_path, ".tmp")
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Archive `git_dir` into a tarball at `path`, gzipped at the `compression` level
+fn write_snapshot_archive(git_dir: &Path, path: &Path, compression: Compression) -> Result<()> {
+    let name = git_dir.file_name().context("Git directory has no name")?;
+
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create snapshot: {}", path.display()))?;
+    let encoder = GzEncoder::new(file, compression);
+    let mut builder = tar::Builder::new(Progress::new(encoder, "Archived"));
+    builder
+        .append_dir_all(name, git_dir)
+        .context("Failed to archive git directory")?;
+    let progress = builder.into_inner().context("Failed to write snapshot")?;
+    progress.finish();
+    // Flush to disk before the archive can be renamed over the previous snapshot
+    let file = progress
+        .inner
+        .finish()
+        .context("Failed to write snapshot")?;
+    file.sync_all().context("Failed to write snapshot")?;
+    Ok(())
+}
+
+/// Bytes processed between progress updates while archiving or extracting
+const PROGRESS_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// Reader/writer wrapper that periodically reports how many bytes have passed
+/// through it, so large snapshots don't look like a hang
+struct Progress<T> {
+    inner: T,
+    label: &'static str,
+    bytes: u64,
+    next_report: u64,
+}
+
+impl<T> Progress<T> {
+    fn new(inner: T, label: &'static str) -> Self {
+        Self {
+            inner,
+            label,
+            bytes: 0,
+            next_report: PROGRESS_INTERVAL,
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.bytes += n as u64;
+        if self.bytes >= self.next_report {
+            self.next_report = self.bytes + PROGRESS_INTERVAL;
+            status!(
+                "#555",
+                "{} {}...",
+                self.label,
+                to_pretty_byte_size(self.bytes)
+            );
+        }
+    }
+
+    /// Report the final total, if any intermediate progress was shown
+    fn finish(&self) {
+        if self.bytes >= PROGRESS_INTERVAL {
+            status!("#555", "{} {}", self.label, to_pretty_byte_size(self.bytes));
+        }
+    }
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.advance(n);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Progress<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.advance(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether a kept `.git` is due to be archived into a fresh snapshot: the
+/// snapshot is missing, or older than `max_age_hours`
+pub(crate) fn snapshot_is_due(snapshot_path: &Path, max_age_hours: Option<u64>) -> bool {
+    let Ok(modified) = fs::metadata(snapshot_path).and_then(|m| m.modified()) else {
+        return true;
+    };
+    max_age_hours.is_some_and(|hours| {
+        modified
+            .elapsed()
+            .is_ok_and(|age| age >= Duration::from_secs(hours.saturating_mul(3600)))
+    })
+}
+
+/// Path of the snapshot for an additional remote: `snapshot_path` with a
+/// slug of the remote URL added to its name, e.g.
+/// `.git-sync-snapshot-gitlab-example-com-team-repo.tar.gz`
+pub(crate) fn remote_snapshot_path(snapshot_path: &Path, remote: &str) -> PathBuf {
+    let remote = remote.split_once("://").map_or(remote, |(_, rest)| rest);
+    let remote = remote.split_once('@').map_or(remote, |(_, rest)| rest);
+    let remote = remote.strip_suffix(".git").unwrap_or(remote);
+    let mut slug = String::new();
+    for c in remote.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    let name = snapshot_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match name.strip_suffix(".tar.gz") {
+        Some(stem) => format!("{}-{}.tar.gz", stem, slug),
+        None => format!("{}-{}", name, slug),
+    };
+    snapshot_path.with_file_name(name)
+}
+
+/// Fail if a new snapshot of `size` bytes is over `max_size`. The sizes are
+/// shown in powers of 1000, as `--max-snapshot-size` is given.
+pub(crate) fn check_snapshot_size(size: u64, max_size: Option<u64>) -> Result<()> {
+    match max_size {
+        Some(max_size) if size > max_size => Err(anyhow::anyhow!(
+            "Snapshot is {}, over the --max-snapshot-size of {}; keeping the previous snapshot",
+            to_pretty_byte_size_with(size, ByteBase::Decimal),
+            to_pretty_byte_size_with(max_size, ByteBase::Decimal)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Path of the `<snapshot>.sha256` checksum sidecar for a snapshot
+pub(crate) fn checksum_path(snapshot_path: &Path) -> PathBuf {
+    with_suffix(snapshot_path, ".sha256")
+}
+
+/// Compute the hex-encoded SHA-256 digest of a file
+fn file_sha256(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read: {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Write the checksum sidecar in `sha256sum` format
+fn write_snapshot_checksum(snapshot_path: &Path) -> Result<()> {
+    let digest = file_sha256(snapshot_path)?;
+    let name = snapshot_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let sidecar = checksum_path(snapshot_path);
+    fs::write(&sidecar, format!("{}  {}\n", digest, name))
+        .with_context(|| format!("Failed to write checksum: {}", sidecar.display()))
+}
+
+/// Check the snapshot against its checksum sidecar, catching truncated or
+/// partially-written snapshots. Snapshots without a sidecar (created by older
+/// versions) are accepted as-is.
+fn verify_snapshot_checksum(snapshot_path: &Path) -> Result<()> {
+    let sidecar = checksum_path(snapshot_path);
+    if !sidecar.exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(&sidecar)
+        .with_context(|| format!("Failed to read checksum: {}", sidecar.display()))?;
+    let expected = contents.split_whitespace().next().unwrap_or_default();
+    let actual = file_sha256(snapshot_path)?;
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(anyhow::anyhow!(
+            "Snapshot {} is corrupt: checksum {} does not match {} recorded in {}",
+            snapshot_path.display(),
+            actual,
+            expected,
+            sidecar.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_backup_restores_unless_kept() {
+        let dir = std::env::temp_dir().join(format!("sea-git-sync-backup-{}", std::process::id()));
+        crate::ensure_clean_dir(&dir).unwrap();
+        let snapshot = dir.join("snapshot.tar.gz");
+        fs::write(&snapshot, "old").unwrap();
+
+        let backup = SnapshotBackup::new(&snapshot).unwrap();
+        fs::write(&snapshot, "new").unwrap();
+        fs::write(checksum_path(&snapshot), "checksum").unwrap();
+        drop(backup);
+        assert_eq!(fs::read_to_string(&snapshot).unwrap(), "old");
+        assert!(!checksum_path(&snapshot).exists());
+
+        let backup = SnapshotBackup::new(&snapshot).unwrap();
+        fs::write(&snapshot, "new").unwrap();
+        backup.keep();
+        assert_eq!(fs::read_to_string(&snapshot).unwrap(), "new");
+        assert!(!backup_path(&snapshot).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover_interrupted_snapshot() {
+        let dir = std::env::temp_dir().join(format!("sea-git-sync-recover-{}", std::process::id()));
+        let git_dir = dir.join(".git");
+        crate::ensure_clean_dir(&git_dir).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        let snapshot = dir.join("snapshot.tar.gz");
+        create_snapshot(&git_dir, &snapshot, None, Compression::default())
+            .unwrap()
+            .keep();
+
+        // Killed while moving the previous snapshot aside for a partly written
+        // one, which skips the restore on drop
+        let partial = temp_path(&snapshot);
+        fs::copy(&snapshot, &partial).unwrap();
+        let truncated = fs::read(&partial).unwrap();
+        fs::write(&partial, &truncated[..truncated.len() / 2]).unwrap();
+        std::mem::forget(SnapshotBackup::new(&snapshot).unwrap());
+        assert!(!snapshot.exists());
+
+        let legacy_partial = with_suffix(&snapshot, ".partial");
+        fs::write(&legacy_partial, "").unwrap();
+
+        recover_interrupted_snapshot(&snapshot).unwrap();
+        assert!(!partial.exists());
+        assert!(!legacy_partial.exists());
+        assert!(!backup_path(&snapshot).exists());
+        assert!(!backup_path(&checksum_path(&snapshot)).exists());
+        let extracted = dir.join("extracted");
+        extract_snapshot(&snapshot, &extracted).unwrap();
+        assert!(extracted.join("HEAD").exists());
+
+        // Killed after the new snapshot was put in place, before its backup
+        // was removed
+        std::mem::forget(SnapshotBackup::new(&snapshot).unwrap());
+        fs::write(&snapshot, "new").unwrap();
+        recover_interrupted_snapshot(&snapshot).unwrap();
+        assert_eq!(fs::read_to_string(&snapshot).unwrap(), "new");
+        assert!(!backup_path(&snapshot).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_compression_levels() {
+        let dir = std::env::temp_dir().join(format!("sea-git-sync-level-{}", std::process::id()));
+        let git_dir = dir.join(".git");
+        crate::ensure_clean_dir(&git_dir).unwrap();
+        fs::write(git_dir.join("config"), "[core]\n".repeat(10_000)).unwrap();
+
+        let stored = dir.join("stored.tar.gz");
+        let best = dir.join("best.tar.gz");
+        write_snapshot_archive(&git_dir, &stored, Compression::none()).unwrap();
+        write_snapshot_archive(&git_dir, &best, Compression::best()).unwrap();
+        let size = |path: &Path| fs::metadata(path).unwrap().len();
+        assert!(size(&stored) > 70_000);
+        assert!(size(&best) < 5_000);
+
+        let extracted = dir.join("extracted");
+        extract_snapshot(&stored, &extracted).unwrap();
+        assert_eq!(
+            fs::read_to_string(extracted.join("config")).unwrap(),
+            "[core]\n".repeat(10_000)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_path() {
+        assert_eq!(
+            checksum_path(Path::new("dir/.git-sync-snapshot.tar.gz")),
+            PathBuf::from("dir/.git-sync-snapshot.tar.gz.sha256")
+        );
+    }
+
+    #[test]
+    fn test_remote_snapshot_path() {
+        assert_eq!(
+            remote_snapshot_path(
+                Path::new("dir/.git-sync-snapshot.tar.gz"),
+                "git@gitlab.example.com:team/repo.git"
+            ),
+            PathBuf::from("dir/.git-sync-snapshot-gitlab-example-com-team-repo.tar.gz")
+        );
+        assert_eq!(
+            remote_snapshot_path(Path::new("snapshot"), "https://github.com/org/repo"),
+            PathBuf::from("snapshot-github-com-org-repo")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_is_due() {
+        let dir = std::env::temp_dir().join(format!("sea-git-sync-due-{}", std::process::id()));
+        ensure_clean_dir(&dir).unwrap();
+        let snapshot = dir.join(".git-sync-snapshot.tar.gz");
+        assert!(snapshot_is_due(&snapshot, None));
+
+        fs::write(&snapshot, "").unwrap();
+        assert!(!snapshot_is_due(&snapshot, None));
+        assert!(!snapshot_is_due(&snapshot, Some(1)));
+        assert!(snapshot_is_due(&snapshot, Some(0)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_progress_counts_bytes() {
+        let mut writer = Progress::new(Vec::new(), "Archived");
+        writer.write_all(b"hello world").unwrap();
+        assert_eq!(writer.bytes, 11);
+
+        let mut reader = Progress::new(&b"hello"[..], "Extracted");
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(reader.bytes, 5);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::config::SyncTarget;
+use crate::git::{StagedChanges, git, git_output, write_git_excludes};
+use crate::glob::{IGNORE_FILE, read_ignore_file};
+use crate::snapshot::extract_snapshot;
+use crate::symlinks::find_symlinks;
+use crate::{
+    SyncConfig, ensure_clean_dir, git_exclude_patterns, in_sync_dir, json_number,
+    json_optional_string, json_string, symlink_patterns,
+};
+use anyhow::Result;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What a sync would find, as reported by [`status`]
+#[derive(Debug, Default)]
+pub struct SyncStatus {
+    /// The state of each remote, in the order they would be synced
+    pub remotes: Vec<RemoteStatus>,
+}
+
+/// The state of one remote's snapshot
+#[derive(Debug, Default)]
+pub struct RemoteStatus {
+    pub remote: String,
+    pub branch: String,
+    pub snapshot_path: PathBuf,
+    /// Size of the snapshot, or `None` if there is no snapshot yet
+    pub snapshot_size_bytes: Option<u64>,
+    /// Time since the snapshot was last written
+    pub snapshot_age: Option<Duration>,
+    /// The commit the snapshot's history ends at
+    pub commit: Option<String>,
+    /// The changes a sync would stage on top of the snapshot
+    pub changes: Option<StagedChanges>,
+}
+
+impl RemoteStatus {
+    /// Whether a sync has anything to commit: there is no snapshot yet, or the
+    /// working tree has changed since it was taken
+    pub fn needs_sync(&self) -> bool {
+        self.changes
+            .as_ref()
+            .is_none_or(|changes| changes.total() > 0)
+    }
+}
+
+impl SyncStatus {
+    /// The status as a single-line JSON object, as printed by `status --output json`
+    pub fn to_json(&self) -> String {
+        let remotes = self
+            .remotes
+            .iter()
+            .map(|remote| {
+                let changes = remote.changes.as_ref().map_or("null".to_string(), |c| {
+                    format!(
+                        "{{\"added\":{},\"modified\":{},\"deleted\":{}}}",
+                        c.added, c.modified, c.deleted
+                    )
+                });
+                format!(
+                    "{{\"remote\":{},\"branch\":{},\"snapshot_path\":{},\"snapshot_size_bytes\":{},\"snapshot_age_secs\":{},\"commit\":{},\"changes\":{},\"needs_sync\":{}}}",
+                    json_string(&remote.remote),
+                    json_string(&remote.branch),
+                    json_string(&remote.snapshot_path.to_string_lossy()),
+                    json_number(remote.snapshot_size_bytes),
+                    json_number(remote.snapshot_age.map(|age| age.as_secs())),
+                    json_optional_string(remote.commit.as_deref()),
+                    changes,
+                    remote.needs_sync()
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("{{\"remotes\":[{}]}}", remotes.join(","))
+    }
+}
+
+/// Report each remote's snapshot, and what a sync of the current directory
+/// (or `config.subdir`) would change in it, without committing or pushing
+/// anything. The working tree is only read: symlinks are left in place, so
+/// files in symlinked directories are not compared.
+pub fn status(config: &SyncConfig) -> Result<SyncStatus> {
+    in_sync_dir(config, |config| {
+        let targets = config.targets()?;
+        let ignored = read_ignore_file(Path::new(IGNORE_FILE))?;
+        let (includes, excludes) = symlink_patterns(config, &ignored)?;
+        let git_excludes = git_exclude_patterns(config, &targets, &ignored);
+        let symlinks = if config.copy_symlinks {
+            find_symlinks(Path::new("."), &includes, &excludes)
+        } else {
+            Vec::new()
+        };
+
+        let remotes = targets
+            .iter()
+            .map(|target| remote_status(target, &git_excludes, &symlinks))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SyncStatus { remotes })
+    })
+}
+
+/// The state of one remote's snapshot, compared against the working tree
+fn remote_status(
+    target: &SyncTarget,
+    git_excludes: &[String],
+    symlinks: &[PathBuf],
+) -> Result<RemoteStatus> {
+    let mut status = RemoteStatus {
+        remote: target.remote.to_string(),
+        branch: target.branch.to_string(),
+        snapshot_path: target.snapshot_path.clone(),
+        ..Default::default()
+    };
+    let Ok(metadata) = fs::metadata(&target.snapshot_path) else {
+        return Ok(status);
+    };
+    status.snapshot_size_bytes = Some(metadata.len());
+    status.snapshot_age = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.elapsed().ok());
+
+    // The snapshot is extracted outside the working tree, so a `.git` already
+    // in it (such as one kept by --keep-git) is left alone
+    let git_dir =
+        std::env::temp_dir().join(format!("sea-git-sync-{}-status.git", std::process::id()));
+    ensure_clean_dir(&git_dir)?;
+    let compared = compare_with_snapshot(&target.snapshot_path, &git_dir, git_excludes, symlinks);
+    let _ = fs::remove_dir_all(&git_dir);
+    let (commit, changes) = compared?;
+    status.commit = commit;
+    status.changes = Some(changes);
+    Ok(status)
+}
+
+/// Extract the snapshot into `git_dir` and stage the working tree in its
+/// index the way a sync would, returning the snapshot's HEAD and the changes
+fn compare_with_snapshot(
+    snapshot_path: &Path,
+    git_dir: &Path,
+    git_excludes: &[String],
+    symlinks: &[PathBuf],
+) -> Result<(Option<String>, StagedChanges)> {
+    extract_snapshot(snapshot_path, git_dir)?;
+    write_git_excludes(git_dir, git_excludes)?;
+
+    let mut git_dir_arg = OsString::from("--git-dir=");
+    git_dir_arg.push(git_dir);
+    let repo = [git_dir_arg, OsString::from("--work-tree=.")];
+    let with_repo = |args: &[&str]| {
+        let mut all = repo.to_vec();
+        all.extend(args.iter().map(OsString::from));
+        all
+    };
+
+    let mut add_args = with_repo(&["add", "--all", "--", "."]);
+    for path in symlinks {
+        let mut exclude = OsString::from(":(exclude)");
+        exclude.push(path);
+        add_args.push(exclude);
+    }
+    git(&add_args)?;
+    let staged = StagedChanges::parse(&git_output(&with_repo(&["status", "--porcelain"]))?);
+    let commit = git_output(&with_repo(&["rev-parse", "HEAD"]))
+        .ok()
+        .map(|sha| sha.trim().to_string());
+    Ok((commit, staged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_to_json() {
+        let status = SyncStatus {
+            remotes: vec![
+                RemoteStatus {
+                    remote: "git@github.com:org/repo.git".to_string(),
+                    branch: "main".to_string(),
+                    snapshot_path: PathBuf::from(".git-sync-snapshot.tar.gz"),
+                    snapshot_size_bytes: Some(2048),
+                    snapshot_age: Some(Duration::from_secs(90)),
+                    commit: Some("4b825dc".to_string()),
+                    changes: Some(StagedChanges::default()),
+                },
+                RemoteStatus {
+                    remote: "https://gitlab.internal/repo".to_string(),
+                    branch: "main".to_string(),
+                    snapshot_path: PathBuf::from("other.tar.gz"),
+                    ..Default::default()
+                },
+            ],
+        };
+        assert!(!status.remotes[0].needs_sync());
+        assert!(status.remotes[1].needs_sync());
+        assert_eq!(
+            status.to_json(),
+            r#"{"remotes":[{"remote":"git@github.com:org/repo.git","branch":"main","snapshot_path":".git-sync-snapshot.tar.gz","snapshot_size_bytes":2048,"snapshot_age_secs":90,"commit":"4b825dc","changes":{"added":0,"modified":0,"deleted":0},"needs_sync":false},{"remote":"https://gitlab.internal/repo","branch":"main","snapshot_path":"other.tar.gz","snapshot_size_bytes":null,"snapshot_age_secs":null,"commit":null,"changes":null,"needs_sync":true}]}"#
+        );
+    }
+}
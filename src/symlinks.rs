@@ -0,0 +1,684 @@
+use crate::glob::{GlobPattern, is_included};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::read_link;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Struct to track replaced symlinks for undoing changes
+#[derive(Debug)]
+pub(crate) struct SymlinkReplacement {
+    symlink_path: PathBuf,
+    /// The link's target as originally written, so it is restored unchanged
+    target: PathBuf,
+    was_dir: bool,
+}
+
+/// Files copied in place of a symlink, grouped by the directory the originals
+/// are in
+#[derive(Debug)]
+pub(crate) struct CopiedFiles {
+    pub(crate) source_dir: PathBuf,
+    /// Each original, relative to `source_dir`, with the path of its copy
+    pub(crate) files: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Replacements shared with the interrupt handler, which restores them if
+/// the sync is interrupted
+pub(crate) type SharedReplacements = Arc<Mutex<Vec<SymlinkReplacement>>>;
+
+/// Symlinks replaced by copies of their targets. The original symlinks are
+/// restored when this is dropped, so the working tree is put back even if the
+/// sync fails partway.
+pub(crate) struct ReplacedSymlinks(SharedReplacements);
+
+impl ReplacedSymlinks {
+    pub(crate) fn new(replacements: Vec<SymlinkReplacement>) -> Self {
+        Self(Arc::new(Mutex::new(replacements)))
+    }
+
+    /// Replace the symlinks under `root` with copies, as by [`copy_symlinks_into`].
+    /// Each one is recorded as soon as it is replaced.
+    pub(crate) fn copy_symlinks(
+        &self,
+        root: &Path,
+        includes: &[GlobPattern],
+        excludes: &[GlobPattern],
+    ) {
+        copy_symlinks_into(root, includes, excludes, &self.0);
+    }
+
+    /// The replacements, for restoring them when interrupted
+    pub(crate) fn shared(&self) -> SharedReplacements {
+        Arc::clone(&self.0)
+    }
+
+    /// The files copied in place of the symlinks, paired with their originals.
+    /// Symlinks nested in a copied directory are listed on their own, with the
+    /// files they were copied from.
+    pub(crate) fn copied_files(&self) -> Vec<CopiedFiles> {
+        fn visit(
+            copy_dir: &Path,
+            rel_dir: &Path,
+            replaced: &HashSet<&Path>,
+            files: &mut Vec<(PathBuf, PathBuf)>,
+        ) {
+            let Ok(entries) = fs::read_dir(copy_dir) else {
+                return;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if replaced.contains(path.as_path()) {
+                    continue;
+                }
+                let rel_path = rel_dir.join(entry.file_name());
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => visit(&path, &rel_path, replaced, files),
+                    Ok(file_type) if file_type.is_file() => files.push((rel_path, path)),
+                    _ => {}
+                }
+            }
+        }
+
+        let replacements = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let replaced = replacements
+            .iter()
+            .map(|rep| rep.symlink_path.as_path())
+            .collect::<HashSet<_>>();
+        replacements
+            .iter()
+            .map(|rep| {
+                let link_dir = rep.symlink_path.parent().unwrap_or(Path::new("."));
+                let source = link_dir.join(&rep.target);
+                if rep.was_dir {
+                    let mut files = Vec::new();
+                    visit(&rep.symlink_path, Path::new(""), &replaced, &mut files);
+                    CopiedFiles {
+                        source_dir: source,
+                        files,
+                    }
+                } else {
+                    let name = source.file_name().map(PathBuf::from).unwrap_or_default();
+                    CopiedFiles {
+                        source_dir: source.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                        files: vec![(name, rep.symlink_path.clone())],
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Paths of the copies that replaced the symlinks
+    pub(crate) fn paths(&self) -> impl Iterator<Item = PathBuf> {
+        let replacements = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let paths = replacements
+            .iter()
+            .map(|rep| rep.symlink_path.clone())
+            .collect::<Vec<_>>();
+        paths.into_iter()
+    }
+}
+
+impl Drop for ReplacedSymlinks {
+    fn drop(&mut self) {
+        let mut replacements = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !replacements.is_empty() {
+            status!("#39C", "Restoring original symlinks...");
+            undo_symlink_replacements(std::mem::take(&mut *replacements));
+        }
+    }
+}
+
+/// Like [`copy_symlinks_into`], returning the replacements made
+#[cfg(test)]
+pub(crate) fn copy_symlinks(
+    root: &Path,
+    includes: &[GlobPattern],
+    excludes: &[GlobPattern],
+) -> Vec<SymlinkReplacement> {
+    let replaced = Mutex::new(Vec::new());
+    copy_symlinks_into(root, includes, excludes, &replaced);
+    replaced
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Replace symlinks under `root` with copies of their target files or directories,
+/// adding info for undoing each change to `replaced` as it is made. Entries matching
+/// any of the exclude patterns are skipped and never descended into, and with include
+/// patterns only included symlinks are replaced.
+/// Symlinks that point at one of their own ancestors, or at a target already being
+/// expanded further up the walk, are left alone to avoid copying an unbounded tree.
+/// The lock is held while a symlink is swapped for its copy, so whoever restores the
+/// symlinks never sees one half done.
+fn copy_symlinks_into(
+    root: &Path,
+    includes: &[GlobPattern],
+    excludes: &[GlobPattern],
+    replaced: &Mutex<Vec<SymlinkReplacement>>,
+) {
+    fn visit_and_replace_symlinks(
+        root: &Path,
+        path: &Path,
+        includes: &[GlobPattern],
+        excludes: &[GlobPattern],
+        replaced: &Mutex<Vec<SymlinkReplacement>>,
+        visited: &mut HashSet<PathBuf>,
+        active_targets: &mut Vec<PathBuf>,
+    ) {
+        let entries = match fs::read_dir(path) {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            let rel_path = entry_path.strip_prefix(root).unwrap_or(&entry_path);
+            if excludes.iter().any(|glob| glob.matches(rel_path)) {
+                continue;
+            }
+            if !visited.insert(entry_path.clone()) {
+                continue;
+            }
+
+            let metadata = match entry.metadata() {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+
+            if is_link(&metadata) {
+                if !is_included(includes, rel_path) {
+                    continue;
+                }
+                let target = match read_link(&entry_path) {
+                    Ok(t) => t,
+                    Err(_) => continue,
+                };
+
+                let abs_target = if target.is_absolute() {
+                    target.clone()
+                } else {
+                    entry_path.parent().unwrap_or(Path::new(".")).join(&target)
+                };
+
+                let target_meta = match fs::metadata(&abs_target) {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+
+                if target_meta.is_dir() {
+                    let abs_target = abs_target.canonicalize().unwrap_or(abs_target);
+                    if is_symlink_cycle(&entry_path, &abs_target, active_targets) {
+                        status!("warn", "Skipping symlink cycle: {}", entry_path.display());
+                        continue;
+                    }
+
+                    let mut replaced_so_far =
+                        replaced.lock().unwrap_or_else(PoisonError::into_inner);
+                    let _ = fs::remove_file(&entry_path);
+                    let _ = copy_dir_all(&abs_target, &entry_path);
+                    replaced_so_far.push(SymlinkReplacement {
+                        symlink_path: entry_path.clone(),
+                        target,
+                        was_dir: true,
+                    });
+                    drop(replaced_so_far);
+                    status!("#555", "{}", entry_path.display());
+
+                    active_targets.push(abs_target);
+                    visit_and_replace_symlinks(
+                        root,
+                        &entry_path,
+                        includes,
+                        excludes,
+                        replaced,
+                        visited,
+                        active_targets,
+                    );
+                    active_targets.pop();
+                } else if target_meta.is_file() {
+                    let mut replaced_so_far =
+                        replaced.lock().unwrap_or_else(PoisonError::into_inner);
+                    let _ = fs::remove_file(&entry_path);
+                    let _ = fs::copy(&abs_target, &entry_path);
+                    replaced_so_far.push(SymlinkReplacement {
+                        symlink_path: entry_path.clone(),
+                        target,
+                        was_dir: false,
+                    });
+                    drop(replaced_so_far);
+                    status!("#555", "{}", entry_path.display());
+                }
+                continue;
+            }
+
+            if metadata.is_dir() {
+                visit_and_replace_symlinks(
+                    root,
+                    &entry_path,
+                    includes,
+                    excludes,
+                    replaced,
+                    visited,
+                    active_targets,
+                );
+            }
+        }
+    }
+
+    /// A link is a cycle if its canonical target contains the link itself
+    /// or is already being expanded higher up the current walk
+    fn is_symlink_cycle(link_path: &Path, canonical_target: &Path, active: &[PathBuf]) -> bool {
+        let canonical_link = link_path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .zip(link_path.file_name())
+            .map(|(parent, name)| parent.join(name));
+        match canonical_link {
+            Some(link) if link.starts_with(canonical_target) => true,
+            Some(_) => active.iter().any(|t| t == canonical_target),
+            None => true,
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut active_targets = Vec::new();
+    visit_and_replace_symlinks(
+        root,
+        root,
+        includes,
+        excludes,
+        replaced,
+        &mut visited,
+        &mut active_targets,
+    );
+}
+
+/// Paths, relative to `root`, of the symlinks that [`copy_symlinks_into`] would
+/// replace at the top level, leaving them in place. Symlinked directories
+/// aren't descended into.
+pub(crate) fn find_symlinks(
+    root: &Path,
+    includes: &[GlobPattern],
+    excludes: &[GlobPattern],
+) -> Vec<PathBuf> {
+    fn visit(
+        root: &Path,
+        path: &Path,
+        includes: &[GlobPattern],
+        excludes: &[GlobPattern],
+        found: &mut Vec<PathBuf>,
+    ) {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            let rel_path = entry_path.strip_prefix(root).unwrap_or(&entry_path);
+            if excludes.iter().any(|glob| glob.matches(rel_path)) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if is_link(&metadata) {
+                if is_included(includes, rel_path) && fs::metadata(&entry_path).is_ok() {
+                    found.push(rel_path.to_path_buf());
+                }
+            } else if metadata.is_dir() {
+                visit(root, &entry_path, includes, excludes, found);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    visit(root, root, includes, excludes, &mut found);
+    found
+}
+
+/// Recursively copy a directory, preserving file permissions (including the
+/// executable bit, so git records the right mode) and directory modes.
+///
+/// The tree is walked first to create every directory, parents before their
+/// children, and the files are then copied in parallel.
+fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let mut dirs = vec![(src.to_path_buf(), dst.to_path_buf())];
+    let mut files = Vec::new();
+    let mut next = 0;
+    while let Some((src_dir, dst_dir)) = dirs.get(next).cloned() {
+        next += 1;
+        fs::create_dir_all(&dst_dir)?;
+        for entry in fs::read_dir(&src_dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let dst_path = dst_dir.join(entry.file_name());
+            if file_type.is_dir() {
+                dirs.push((entry.path(), dst_path));
+            } else if file_type.is_file() {
+                files.push((entry.path(), dst_path));
+            }
+        }
+    }
+
+    files.par_iter().try_for_each(|(src_path, dst_path)| {
+        fs::copy(src_path, dst_path)?;
+        fs::set_permissions(dst_path, fs::metadata(src_path)?.permissions())
+    })?;
+
+    // Children first, so a directory's mode is only applied once it is filled
+    for (src_dir, dst_dir) in dirs.iter().rev() {
+        fs::set_permissions(dst_dir, dir_permissions(&fs::metadata(src_dir)?))?;
+    }
+    Ok(())
+}
+
+/// Add an empty `.gitkeep` file to every empty directory under `dir`, since git
+/// only records directories that contain files. Only meant for copies, which
+/// are removed again with the rest of the copy when the symlink is restored.
+pub(crate) fn add_gitkeep_files(dir: &Path) -> std::io::Result<()> {
+    if !fs::symlink_metadata(dir)?.is_dir() {
+        return Ok(());
+    }
+    let mut is_empty = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        is_empty = false;
+        if entry.file_type()?.is_dir() {
+            add_gitkeep_files(&entry.path())?;
+        }
+    }
+    if is_empty {
+        fs::write(dir.join(".gitkeep"), "")?;
+    }
+    Ok(())
+}
+
+/// Permissions for a copied directory: the source's mode, but always writable
+/// and searchable by the owner so the copy can be removed again afterwards
+#[cfg(unix)]
+fn dir_permissions(src_meta: &fs::Metadata) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    fs::Permissions::from_mode(src_meta.permissions().mode() | 0o700)
+}
+
+#[cfg(not(unix))]
+fn dir_permissions(src_meta: &fs::Metadata) -> fs::Permissions {
+    let mut permissions = src_meta.permissions();
+    permissions.set_readonly(false);
+    permissions
+}
+
+/// Undo the symlink replacements, restoring the original symlinks
+pub(crate) fn undo_symlink_replacements(replacements: Vec<SymlinkReplacement>) {
+    for rep in replacements {
+        if rep.was_dir {
+            let _ = fs::remove_dir_all(&rep.symlink_path);
+        } else {
+            let _ = fs::remove_file(&rep.symlink_path);
+        }
+        status!("#555", "{}", rep.symlink_path.display());
+        let _ = restore_symlink(&rep);
+    }
+}
+
+#[cfg(unix)]
+fn restore_symlink(rep: &SymlinkReplacement) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(&rep.target, &rep.symlink_path)
+}
+
+#[cfg(windows)]
+fn restore_symlink(rep: &SymlinkReplacement) -> std::io::Result<()> {
+    if rep.was_dir {
+        std::os::windows::fs::symlink_dir(&rep.target, &rep.symlink_path)
+    } else {
+        std::os::windows::fs::symlink_file(&rep.target, &rep.symlink_path)
+    }
+}
+
+/// Whether the (non-followed) metadata describes a link to be replaced
+#[cfg(unix)]
+fn is_link(metadata: &fs::Metadata) -> bool {
+    metadata.file_type().is_symlink()
+}
+
+/// Whether the (non-followed) metadata describes a link to be replaced.
+/// On Windows this includes junctions and other reparse points.
+#[cfg(windows)]
+fn is_link(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    metadata.file_type().is_symlink()
+        || metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ensure_clean_dir;
+
+    /// Create an empty scratch directory unique to the calling test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sea-git-sync-{}-{}", name, std::process::id()));
+        ensure_clean_dir(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_dir_all_preserves_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = scratch_dir("copy-permissions");
+        fs::create_dir_all(root.join("src/bin")).unwrap();
+        let script = root.join("src/bin/run.sh");
+        fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(root.join("src/bin"), fs::Permissions::from_mode(0o750)).unwrap();
+
+        copy_dir_all(&root.join("src"), &root.join("dst")).unwrap();
+
+        let mode = fs::metadata(root.join("dst/bin/run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        let mode = fs::metadata(root.join("dst/bin"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_copy_dir_all_copies_large_tree() {
+        let root = scratch_dir("copy-large");
+        let src = root.join("src");
+        for dir in 0..20 {
+            let dir_path = src.join(format!("dir{}/nested{}", dir, dir % 3));
+            fs::create_dir_all(&dir_path).unwrap();
+            for file in 0..50 {
+                fs::write(
+                    dir_path.join(format!("{}.txt", file)),
+                    format!("{}-{}", dir, file),
+                )
+                .unwrap();
+            }
+        }
+
+        let dst = root.join("dst");
+        copy_dir_all(&src, &dst).unwrap();
+        for dir in 0..20 {
+            let dir_path = dst.join(format!("dir{}/nested{}", dir, dir % 3));
+            assert_eq!(fs::read_dir(&dir_path).unwrap().count(), 50);
+            for file in 0..50 {
+                let contents = fs::read_to_string(dir_path.join(format!("{}.txt", file))).unwrap();
+                assert_eq!(contents, format!("{}-{}", dir, file));
+            }
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_add_gitkeep_files_fills_empty_dirs() {
+        let root = scratch_dir("gitkeep");
+        fs::create_dir_all(root.join("empty/nested")).unwrap();
+        fs::create_dir_all(root.join("full")).unwrap();
+        fs::write(root.join("full/file.txt"), "hello").unwrap();
+
+        add_gitkeep_files(&root).unwrap();
+        assert!(root.join("empty/nested/.gitkeep").exists());
+        assert!(!root.join("empty/.gitkeep").exists());
+        assert!(!root.join("full/.gitkeep").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_symlinks_skips_cycle_to_parent() {
+        let root = scratch_dir("symlink-cycle");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir/file.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink("..", root.join("dir/back")).unwrap();
+
+        let replaced = copy_symlinks(&root, &[], &[]);
+        assert!(replaced.is_empty());
+        assert!(is_link(
+            &fs::symlink_metadata(root.join("dir/back")).unwrap()
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_symlinks_replaces_sibling_dir() {
+        let root = scratch_dir("symlink-sibling");
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("shared/file.txt"), "hello").unwrap();
+        fs::create_dir_all(root.join("project")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("project/shared")).unwrap();
+
+        let replaced = copy_symlinks(&root, &[], &[]);
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(
+            fs::read_to_string(root.join("project/shared/file.txt")).unwrap(),
+            "hello"
+        );
+
+        undo_symlink_replacements(replaced);
+        assert_eq!(
+            read_link(root.join("project/shared")).unwrap(),
+            Path::new("../shared")
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_symlinks_leaves_links_in_place() {
+        let root = scratch_dir("symlink-find");
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::create_dir_all(root.join("project/docs")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("project/shared")).unwrap();
+        std::os::unix::fs::symlink("../../shared", root.join("project/docs/shared")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("project/dangling")).unwrap();
+
+        let project = root.join("project");
+        let mut found = find_symlinks(&project, &[], &[]);
+        found.sort();
+        assert_eq!(found, [Path::new("docs/shared"), Path::new("shared")]);
+        assert!(is_link(
+            &fs::symlink_metadata(project.join("shared")).unwrap()
+        ));
+
+        let excludes = [GlobPattern::new("docs").unwrap()];
+        assert_eq!(
+            find_symlinks(&project, &[], &excludes),
+            [Path::new("shared")]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_symlinks_replaces_file() {
+        let root = scratch_dir("symlink-file");
+        fs::create_dir_all(root.join("project")).unwrap();
+        fs::write(root.join("LICENSE"), "MIT").unwrap();
+        std::os::unix::fs::symlink("../LICENSE", root.join("project/LICENSE")).unwrap();
+
+        let replaced = copy_symlinks(&root, &[], &[]);
+        assert_eq!(replaced.len(), 1);
+        let metadata = fs::symlink_metadata(root.join("project/LICENSE")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(
+            fs::read_to_string(root.join("project/LICENSE")).unwrap(),
+            "MIT"
+        );
+
+        undo_symlink_replacements(replaced);
+        assert_eq!(
+            read_link(root.join("project/LICENSE")).unwrap(),
+            Path::new("../LICENSE")
+        );
+        assert_eq!(fs::read_to_string(root.join("LICENSE")).unwrap(), "MIT");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replaced_symlinks_restored_on_error() {
+        fn sync_that_fails(root: &Path) -> anyhow::Result<()> {
+            let replaced = ReplacedSymlinks::new(copy_symlinks(root, &[], &[]));
+            assert_eq!(replaced.paths().count(), 1);
+            assert!(!is_link(
+                &fs::symlink_metadata(root.join("project/shared")).unwrap()
+            ));
+            Err(anyhow::anyhow!("injected failure"))
+        }
+
+        let root = scratch_dir("symlink-guard");
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("shared/file.txt"), "hello").unwrap();
+        fs::create_dir_all(root.join("project")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("project/shared")).unwrap();
+
+        assert!(sync_that_fails(&root).is_err());
+        assert!(is_link(
+            &fs::symlink_metadata(root.join("project/shared")).unwrap()
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_symlinks_only_replaces_included() {
+        let root = scratch_dir("symlink-include");
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("shared/file.txt"), "hello").unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("src/shared")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("docs/shared")).unwrap();
+
+        let includes = [GlobPattern::new("src/shared").unwrap()];
+        let replaced = copy_symlinks(&root, &includes, &[]);
+        assert_eq!(replaced.len(), 1);
+        assert!(is_link(
+            &fs::symlink_metadata(root.join("docs/shared")).unwrap()
+        ));
+
+        undo_symlink_replacements(replaced);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
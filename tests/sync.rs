@@ -0,0 +1,369 @@
+//! End-to-end syncs against a local bare repository
+
+use sea_git_sync::{LfsMode, MergeStrategy, SyncConfig, status, sync};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
+
+const IDENTITY: &str = "Sync Test <sync@example.com>";
+
+/// Held by each test, as a sync changes the process's working directory
+static SYNC_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock() -> MutexGuard<'static, ()> {
+    SYNC_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args([
+            "-c",
+            "user.name=Sync Test",
+            "-c",
+            "user.email=sync@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A bare repository with one commit on `main`, to sync to
+fn create_remote(root: &Path) -> PathBuf {
+    let remote = root.join("remote.git");
+    let seed = root.join("seed");
+    fs::create_dir_all(&seed).unwrap();
+    git(
+        root,
+        &[
+            "init",
+            "--quiet",
+            "--bare",
+            "--initial-branch=main",
+            "remote.git",
+        ],
+    );
+    git(&seed, &["init", "--quiet", "--initial-branch=main"]);
+    fs::write(seed.join("README.md"), "mirror\n").unwrap();
+    git(&seed, &["add", "."]);
+    git(&seed, &["commit", "--quiet", "-m", "Initial commit"]);
+    git(
+        &seed,
+        &["push", "--quiet", remote.to_str().unwrap(), "main"],
+    );
+    remote
+}
+
+/// A temporary directory holding a remote to sync to and a `project` folder
+/// to sync, along with a config for syncing it
+fn setup(name: &str) -> (PathBuf, SyncConfig) {
+    let root = std::env::temp_dir().join(format!("sea-git-sync-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let remote = create_remote(&root);
+    let project = root.join("project");
+    fs::create_dir_all(&project).unwrap();
+
+    let mut config = SyncConfig::new(remote.to_str().unwrap());
+    config.subdir = Some(project);
+    config.author = Some(IDENTITY.to_string());
+    config.committer = Some(IDENTITY.to_string());
+    config.quiet = true;
+    (root, config)
+}
+
+/// The files on the remote's `main` branch
+fn remote_files(root: &Path) -> Vec<String> {
+    let files = git(
+        &root.join("remote.git"),
+        &["ls-tree", "-r", "--name-only", "main"],
+    );
+    files.lines().map(str::to_string).collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_prune_deletes_file_removed_from_symlinked_dir() {
+    let _lock = lock();
+    let (root, mut config) = setup("prune");
+    let project = root.join("project");
+    let shared = root.join("shared");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("kept.txt"), "kept\n").unwrap();
+    fs::write(shared.join("removed.txt"), "removed\n").unwrap();
+    std::os::unix::fs::symlink("../shared", project.join("shared")).unwrap();
+
+    config.prune = true;
+    sync(&config).unwrap();
+
+    fs::remove_file(shared.join("removed.txt")).unwrap();
+    let report = sync(&config).unwrap();
+    assert!(report.push_succeeded);
+
+    let files = remote_files(&root);
+    assert!(files.contains(&"shared/kept.txt".to_string()));
+    assert!(!files.contains(&"shared/removed.txt".to_string()));
+    assert!(
+        fs::symlink_metadata(project.join("shared"))
+            .unwrap()
+            .is_symlink()
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hooks_run_around_the_sync() {
+    let _lock = lock();
+    let (root, mut config) = setup("hooks");
+    let project = root.join("project");
+    fs::write(project.join("main.rs"), "fn main() {}\n").unwrap();
+
+    config.pre_commit_hook = Some("echo '// generated' > header.txt".to_string());
+    config.post_sync_hook = Some("touch ../post-sync-ran".to_string());
+    sync(&config).unwrap();
+    assert!(remote_files(&root).contains(&"header.txt".to_string()));
+    assert!(root.join("post-sync-ran").exists());
+
+    fs::write(project.join("main.rs"), "fn main() { todo!() }\n").unwrap();
+    config.pre_commit_hook = Some("exit 3".to_string());
+    let err = sync(&config).unwrap_err();
+    assert!(format!("{:#}", err).contains("pre-commit hook failed"));
+    let log = git(&root.join("remote.git"), &["log", "--format=%s", "main"]);
+    assert_eq!(log.lines().count(), 2);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_syncs_non_utf8_symlink() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let _lock = lock();
+    let (root, config) = setup("non-utf8");
+    let shared = root.join("shared");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("data.txt"), "data\n").unwrap();
+    let name = OsStr::from_bytes(b"caf\xe9");
+    std::os::unix::fs::symlink("../shared", root.join("project").join(name)).unwrap();
+
+    sync(&config).unwrap();
+
+    let output = Command::new("git")
+        .args(["ls-tree", "-r", "-z", "--name-only", "main"])
+        .current_dir(root.join("remote.git"))
+        .output()
+        .unwrap();
+    assert!(
+        output
+            .stdout
+            .split(|&b| b == 0)
+            .any(|file| file == b"caf\xe9/data.txt")
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_status_compares_with_snapshot() {
+    let _lock = lock();
+    let (root, config) = setup("status");
+    let project = root.join("project");
+    let shared = root.join("shared");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("data.txt"), "data\n").unwrap();
+    std::os::unix::fs::symlink("../shared", project.join("shared")).unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}\n").unwrap();
+
+    let remote = &status(&config).unwrap().remotes[0];
+    assert!(remote.snapshot_size_bytes.is_none());
+    assert!(remote.needs_sync());
+
+    let report = sync(&config).unwrap();
+    let remote = &status(&config).unwrap().remotes[0];
+    assert!(remote.snapshot_size_bytes.is_some());
+    assert_eq!(remote.commit, report.commit);
+    assert!(!remote.needs_sync());
+
+    fs::write(project.join("main.rs"), "fn main() { todo!() }\n").unwrap();
+    fs::write(project.join("lib.rs"), "").unwrap();
+    let changes = status(&config).unwrap().remotes[0].changes.take().unwrap();
+    assert_eq!(
+        (changes.added, changes.modified, changes.deleted),
+        (1, 1, 0)
+    );
+    assert!(
+        fs::symlink_metadata(project.join("shared"))
+            .unwrap()
+            .is_symlink()
+    );
+    assert!(!project.join(".git").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_branch_prefix_mirrors_the_current_branch() {
+    let _lock = lock();
+    let (root, mut config) = setup("branch-prefix");
+    fs::write(root.join("project/notes.txt"), "notes\n").unwrap();
+    // The temporary directory doubles as the monorepo the project is in
+    git(&root, &["init", "--quiet", "--initial-branch=release/1.0"]);
+    git(
+        &root,
+        &["commit", "--quiet", "--allow-empty", "-m", "Monorepo"],
+    );
+
+    config.branches = Vec::new();
+    config.branch_prefix = Some("mirror/".to_string());
+    config.branch_create = true;
+    let report = sync(&config).unwrap();
+    assert!(report.push_succeeded);
+    assert_eq!(report.remotes[0].branch, "mirror/release-1.0");
+
+    let files = git(
+        &root.join("remote.git"),
+        &["ls-tree", "-r", "--name-only", "mirror/release-1.0"],
+    );
+    assert!(files.lines().any(|file| file == "notes.txt"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lfs_pointer_replaces_lfs_files() {
+    let _lock = lock();
+    let (root, mut config) = setup("lfs");
+    let project = root.join("project");
+    let shared = root.join("shared");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("model.bin"), "weights\n").unwrap();
+    fs::write(shared.join("notes.txt"), "notes\n").unwrap();
+    std::os::unix::fs::symlink("../shared", project.join("shared")).unwrap();
+    // The temporary directory doubles as the monorepo, tracking *.bin with LFS
+    git(&root, &["init", "--quiet", "--initial-branch=main"]);
+    fs::write(root.join(".gitattributes"), "*.bin filter=lfs\n").unwrap();
+
+    config.lfs = LfsMode::Pointer;
+    let report = sync(&config).unwrap();
+    assert!(report.push_succeeded);
+
+    let remote = root.join("remote.git");
+    let pointer = git(&remote, &["show", "main:shared/model.bin"]);
+    assert!(pointer.starts_with("version https://git-lfs.github.com/spec/v1\n"));
+    assert!(pointer.ends_with("size 8\n"));
+    assert_eq!(git(&remote, &["show", "main:shared/notes.txt"]), "notes\n");
+    assert_eq!(
+        fs::read_to_string(shared.join("model.bin")).unwrap(),
+        "weights\n"
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_commit_empty_pushes_a_commit_without_changes() {
+    let _lock = lock();
+    let (root, mut config) = setup("commit-empty");
+    fs::write(root.join("project/notes.txt"), "notes\n").unwrap();
+    sync(&config).unwrap();
+    let remote = root.join("remote.git");
+    let before = git(&remote, &["rev-parse", "main"]);
+
+    let report = sync(&config).unwrap();
+    assert!(!report.commit_created);
+    assert_eq!(git(&remote, &["rev-parse", "main"]), before);
+
+    config.commit_empty = true;
+    let report = sync(&config).unwrap();
+    assert!(report.commit_created);
+    assert!(report.push_succeeded);
+    assert_eq!(report.files_added, 0);
+    assert_eq!(git(&remote, &["rev-parse", "main^"]), before);
+    assert_eq!(git(&remote, &["diff", "--name-only", "main^", "main"]), "");
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_failed_sync_keeps_git_only_for_a_conflict() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _lock = lock();
+    let (root, config) = setup("pull-failure");
+    let project = root.join("project");
+    let remote = root.join("remote.git");
+    fs::write(project.join("notes.txt"), "synced\n").unwrap();
+    sync(&config).unwrap();
+
+    // The mirror changes the same line as the monorepo
+    let clone = root.join("clone");
+    git(
+        &root,
+        &["clone", "--quiet", remote.to_str().unwrap(), "clone"],
+    );
+    fs::write(clone.join("notes.txt"), "mirror\n").unwrap();
+    git(&clone, &["commit", "--quiet", "-am", "Edit in the mirror"]);
+    git(&clone, &["push", "--quiet", "origin", "main"]);
+    fs::write(project.join("notes.txt"), "monorepo\n").unwrap();
+    // A merge needs an identity (even to find the conflict), and --author and
+    // --committer only apply to the sync commit
+    // SAFETY: the tests in this file hold SYNC_LOCK, and none of them reads
+    // the environment other than through std
+    unsafe {
+        for var in ["GIT_AUTHOR", "GIT_COMMITTER"] {
+            std::env::set_var(format!("{}_NAME", var), "Sync Test");
+            std::env::set_var(format!("{}_EMAIL", var), "sync@example.com");
+        }
+    }
+    // Retrying can't resolve the conflict, so it is reported without waiting
+    let mut retried = config.clone();
+    retried.retries = 3;
+    retried.retry_delay = 60;
+    let started = std::time::Instant::now();
+    let error = sync(&retried).unwrap_err();
+    assert!(started.elapsed().as_secs() < 30);
+    assert!(!format!("{:#}", error).contains("not concluded your merge"));
+    assert!(project.join(".git/MERGE_HEAD").exists());
+    fs::remove_dir_all(project.join(".git")).unwrap();
+
+    // Neither is a pull that refuses to merge at all
+    let mut ff_only = config.clone();
+    ff_only.merge_strategy = MergeStrategy::FfOnly;
+    assert!(sync(&ff_only).is_err());
+    assert!(!project.join(".git").exists());
+
+    // Nor a push that the remote rejects
+    fs::write(project.join("notes.txt"), "mirror\n").unwrap();
+    let hook = remote.join("hooks/pre-receive");
+    fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(sync(&config).is_err());
+    assert!(!project.join(".git").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_log_records_the_expanded_message() {
+    let _lock = lock();
+    let (root, mut config) = setup("log-message");
+    fs::write(root.join("project/notes.txt"), "notes\n").unwrap();
+    let log_file = root.join("sync.log");
+    config.log_file = Some(log_file.clone());
+    config.message = "Sync {count} files".to_string();
+    sync(&config).unwrap();
+
+    let subject = git(
+        &root.join("remote.git"),
+        &["log", "-1", "--format=%s", "main"],
+    );
+    assert!(subject.starts_with("Sync ") && !subject.contains("{count}"));
+    let log = fs::read_to_string(&log_file).unwrap();
+    assert!(
+        log.contains(&format!("message={:?}", subject.trim())),
+        "{}",
+        log
+    );
+    fs::remove_dir_all(&root).unwrap();
+}